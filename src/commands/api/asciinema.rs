@@ -13,6 +13,8 @@ use std::io::Write;
 use std::path::PathBuf;
 use uuid::Uuid;
 
+const DEFAULT_API_SERVER: &str = "https://asciinema.org";
+
 #[derive(Serialize, Deserialize)]
 struct Config {
     #[serde(rename = "install_id")]
@@ -24,12 +26,17 @@ struct Config {
 }
 
 impl Config {
-    fn get_config_file() -> (PathBuf, PathBuf) {
-        let app_dirs = AppDirs::new(None, true).unwrap();
+    /// The config directory and file, or `None` if there is no config
+    /// directory for the user.
+    fn config_paths() -> Option<(PathBuf, PathBuf)> {
+        let app_dirs = AppDirs::new(None, true)?;
         let config_root = app_dirs.config_dir.join("PowerSession");
         let config_file = config_root.join("config.json");
+        Some((config_root, config_file))
+    }
 
-        return (config_root, config_file);
+    fn get_config_file() -> (PathBuf, PathBuf) {
+        Self::config_paths().unwrap()
     }
 
     fn get() -> Self {
@@ -48,6 +55,12 @@ impl Config {
             return Self::new(None);
         };
     }
+    /// Reads the config file if there is one, without creating it.
+    fn read() -> Option<Self> {
+        let (_, config_file) = Self::config_paths()?;
+        serde_json::from_str(&fs::read_to_string(config_file).ok()?).ok()
+    }
+
     fn new(api_server: Option<String>) -> Self {
         let (config_root, config_file) = Self::get_config_file();
 
@@ -60,7 +73,7 @@ impl Config {
             install_id = Self::get().install_id;
         }
         // Initialize with default if no value given
-        let api_server = api_server.unwrap_or("https://asciinema.org".to_string());
+        let api_server = api_server.unwrap_or(DEFAULT_API_SERVER.to_string());
         let c = Config {
             install_id,
             api_server,
@@ -141,6 +154,14 @@ impl Asciinema {
     pub fn change_server(api_server: String) {
         Config::change_api_server(api_server)
     }
+
+    /// The configured server's URL, or the default one. Unlike `new`, this
+    /// neither creates the config file nor prints anything.
+    pub fn configured_server_url() -> String {
+        Config::read()
+            .map(|config| config.api_server.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| DEFAULT_API_SERVER.to_string())
+    }
 }
 
 impl ApiService for Asciinema {
@@ -248,6 +269,17 @@ impl ApiService for Asciinema {
         format!("{}/ws/S/{}", base, stream_id)
     }
 
+    fn get_stream_watch_url(&self, stream_id: &str) -> String {
+        // Viewers connect to /ws/s/<stream_id>, next to the producer endpoint.
+        let base = self
+            .config
+            .api_server
+            .trim_end_matches('/')
+            .replace("https://", "wss://")
+            .replace("http://", "ws://");
+        format!("{}/ws/s/{}", base, stream_id)
+    }
+
     fn get_auth_header(&self) -> String {
         let cred = format!("user:{}", self.config.install_id);
         format!("Basic {}", BASE64_STANDARD.encode(&cred))
@@ -286,6 +318,10 @@ mod tests {
             asc_https.get_stream_ws_url("abc123"),
             "wss://demo.asciinema.org/ws/S/abc123"
         );
+        assert_eq!(
+            asc_https.get_stream_watch_url("abc123"),
+            "wss://demo.asciinema.org/ws/s/abc123"
+        );
 
         let asc_http = super::Asciinema {
             config: Config {
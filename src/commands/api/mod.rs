@@ -14,6 +14,8 @@ pub trait ApiService {
     fn create_stream(&self, cols: u16, rows: u16) -> Option<StreamInfo>;
     /// Build the WebSocket producer URL for an *existing* stream identified by `stream_id`.
     fn get_stream_ws_url(&self, stream_id: &str) -> String;
+    /// Build the WebSocket URL viewers connect to for the stream identified by `stream_id`.
+    fn get_stream_watch_url(&self, stream_id: &str) -> String;
     /// Return the `Authorization` header value to authenticate WebSocket connections.
     fn get_auth_header(&self) -> String;
 }
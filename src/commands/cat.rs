@@ -0,0 +1,145 @@
+use crate::commands::escape::{EscapeParser, Token};
+use crate::commands::fetch::FetchOptions;
+use crate::commands::session::Session;
+
+use std::io;
+use std::io::Write;
+use std::process::exit;
+
+/// Writes the recorded output of sessions to stdout without any timing.
+pub struct Cat {
+    sources: Vec<String>,
+    plain: bool,
+}
+
+impl Cat {
+    pub fn new(sources: Vec<String>, plain: bool) -> Self {
+        Cat { sources, plain }
+    }
+
+    pub fn execute(self) {
+        let mut stdout = io::stdout().lock();
+
+        for source in &self.sources {
+            let mut stripper = AnsiStripper::default();
+            for event in Session::open_or_exit(source, &FetchOptions::default()).event_iter(&["o"]) {
+                let event = event.unwrap_or_else(|e| {
+                    eprintln!("'{}': {}", source, e);
+                    exit(1);
+                });
+                let data = if self.plain {
+                    stripper.strip(&event.content)
+                } else {
+                    event.content
+                };
+                write_or_exit(&mut stdout, &data);
+            }
+            write_or_exit(&mut stdout, &stripper.finish());
+        }
+    }
+}
+
+/// Writes `data` and flushes, exiting quietly when the reader went away
+/// (e.g. `less` was closed) and with an error otherwise.
+fn write_or_exit(stdout: &mut impl Write, data: &str) {
+    if let Err(e) = stdout
+        .write_all(data.as_bytes())
+        .and_then(|_| stdout.flush())
+    {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            exit(0);
+        }
+        eprintln!("error writing output: {}", e);
+        exit(1);
+    }
+}
+
+/// Removes ANSI escape sequences and other control characters from output,
+/// keeping line breaks and tabs. Cursor moves to another line become line
+/// breaks.
+struct AnsiStripper {
+    parser: EscapeParser,
+    line_start: bool,
+    /// A carriage return is held back, as it is dropped before a line feed.
+    carriage_return: bool,
+}
+
+impl Default for AnsiStripper {
+    fn default() -> Self {
+        AnsiStripper {
+            parser: EscapeParser::default(),
+            line_start: true,
+            carriage_return: false,
+        }
+    }
+}
+
+impl AnsiStripper {
+    fn strip(&mut self, data: &str) -> String {
+        let mut out = String::with_capacity(data.len());
+        for c in data.chars() {
+            if std::mem::take(&mut self.carriage_return) && c != '\n' {
+                out.push('\r');
+            }
+            match self.parser.feed(c) {
+                Some(Token::Char('\r')) => self.carriage_return = true,
+                Some(Token::Char(c @ ('\n' | '\t'))) => out.push(c),
+                Some(Token::Char('\x00'..='\x1f' | '\x7f')) => {}
+                Some(Token::Char(c)) => out.push(c),
+                // Cursor position and next line.
+                Some(Token::Csi(sequence)) if sequence.ends_with(['H', 'f', 'E']) => {
+                    let line_start = match out.chars().last() {
+                        Some(last) => last == '\n',
+                        None => self.line_start,
+                    };
+                    if !line_start {
+                        out.push('\n');
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(last) = out.chars().last() {
+            self.line_start = last == '\n';
+        }
+        out
+    }
+
+    /// Returns what is held back at the end of a session.
+    fn finish(&mut self) -> String {
+        if std::mem::take(self).carriage_return {
+            "\r".to_string()
+        } else {
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnsiStripper;
+
+    #[test]
+    fn test_strip() {
+        let mut stripper = AnsiStripper::default();
+        assert_eq!(
+            stripper.strip("\x1b[2J\x1b[m\x1b[HPS C:\\> \x1b[32mls\x1b[0m\r\n"),
+            "PS C:\\> ls\n"
+        );
+        assert_eq!(stripper.strip("a\x1b[4;1Hb\r\n\x1b[6;1H"), "a\nb\n");
+        assert_eq!(stripper.strip("\x1b]0;title\x07a\tb\x07"), "a\tb");
+        assert_eq!(stripper.strip("\x1bP1$r0m\x1b\\c\x1b(Bd"), "cd");
+        assert_eq!(stripper.strip("50%\r100%"), "50%\r100%");
+    }
+
+    #[test]
+    fn test_strip_across_events() {
+        let mut stripper = AnsiStripper::default();
+        assert_eq!(stripper.strip("a\x1b["), "a");
+        assert_eq!(stripper.strip("31mb\x1b]0;ti"), "b");
+        assert_eq!(stripper.strip("tle\x07c\r"), "c");
+        assert_eq!(stripper.strip("\n"), "\n");
+        assert_eq!(stripper.strip("d\r"), "d");
+        assert_eq!(stripper.finish(), "\r");
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+/// Parse a duration such as `90`, `90s`, `30m`, `2h` or `1h30m`. A bare
+/// number is taken as seconds. Zero is rejected, as no limit or timeout
+/// makes sense at zero.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    match parse_seconds(s)? {
+        Duration::ZERO => Err(format!(
+            "invalid duration `{}`, must be more than zero",
+            s.trim()
+        )),
+        duration => Ok(duration),
+    }
+}
+
+/// Parse a duration as [`parse_duration`] does, zero included.
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let invalid = || {
+        format!(
+            "invalid duration `{}`, expected e.g. 90s, 30m, 2h or 1h30m",
+            s
+        )
+    };
+
+    let seconds = match s.parse::<f64>() {
+        Ok(seconds) => seconds,
+        Err(_) => {
+            let mut seconds = 0.0;
+            let mut number = String::new();
+            for c in s.chars() {
+                let unit = match c {
+                    '0'..='9' | '.' => {
+                        number.push(c);
+                        continue;
+                    }
+                    'h' => 3600.0,
+                    'm' => 60.0,
+                    's' => 1.0,
+                    _ => return Err(invalid()),
+                };
+                seconds += number.parse::<f64>().map_err(|_| invalid())? * unit;
+                number.clear();
+            }
+            if !number.is_empty() || s.is_empty() {
+                return Err(invalid());
+            }
+            seconds
+        }
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+/// Parse a playback position such as `83`, `1:23`, `1:02:03.5` or `1m23s`.
+pub fn parse_timestamp(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if !s.contains(':') {
+        return parse_seconds(s);
+    }
+
+    let invalid = || format!("invalid position `{}`, expected e.g. 83, 1:23 or 1:02:03", s);
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0.0;
+    for part in parts {
+        match part.parse::<f64>() {
+            Ok(value) if value >= 0.0 => seconds = seconds * 60.0 + value,
+            _ => return Err(invalid()),
+        }
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+/// Format a duration the way [`parse_duration`] accepts it, e.g. `1h30m`.
+pub(crate) fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    let mut out = String::new();
+    if h > 0 {
+        out.push_str(&format!("{}h", h));
+    }
+    if m > 0 {
+        out.push_str(&format!("{}m", m));
+    }
+    if s > 0 || out.is_empty() {
+        out.push_str(&format!("{}s", s));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, parse_duration, parse_timestamp};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 3600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("0.5h"), Ok(Duration::from_secs(1800)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("2d").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("-5").is_err());
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("0h0m").is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("83"), Ok(Duration::from_secs(83)));
+        assert_eq!(parse_timestamp("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_timestamp("1:23"), Ok(Duration::from_secs(83)));
+        assert_eq!(parse_timestamp("1:02:03"), Ok(Duration::from_secs(3723)));
+        assert_eq!(parse_timestamp("0:01.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_timestamp("1m23s"), Ok(Duration::from_secs(83)));
+        assert!(parse_timestamp("1::3").is_err());
+        assert!(parse_timestamp("1:2:3:4").is_err());
+        assert!(parse_timestamp("1:-2").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+        assert_eq!(format_duration(Duration::from_secs(5430)), "1h30m30s");
+    }
+}
@@ -0,0 +1,438 @@
+use encoding_rs::{BIG5, Decoder, EUC_KR, Encoding, GBK, SHIFT_JIS, UTF_8};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long an incomplete UTF-8 sequence may wait for its continuation bytes
+/// before it is treated as invalid and flushed according to the policy.
+pub(crate) const PENDING_FLUSH_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// What to do with child output that is not valid UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidUtf8Policy {
+    /// Substitute U+FFFD for every invalid sequence.
+    Replace,
+    /// Render every invalid byte as a `\xNN` escape.
+    Escape,
+    /// Discard invalid bytes.
+    Drop,
+}
+
+impl FromStr for InvalidUtf8Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(InvalidUtf8Policy::Replace),
+            "escape" => Ok(InvalidUtf8Policy::Escape),
+            "drop" => Ok(InvalidUtf8Policy::Drop),
+            _ => Err(format!(
+                "unknown policy `{}`, expected one of [replace|escape|drop]",
+                s
+            )),
+        }
+    }
+}
+
+/// Incremental UTF-8 decoder for pty output.
+///
+/// Sequences split across read boundaries are held back until the rest of the
+/// sequence arrives; bytes that can never become valid UTF-8 are rendered
+/// immediately according to the configured policy.
+pub(crate) struct Utf8Decoder {
+    policy: InvalidUtf8Policy,
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub(crate) fn new(policy: InvalidUtf8Policy) -> Self {
+        Utf8Decoder {
+            policy,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Whether an incomplete sequence is waiting for more bytes.
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Decode `bytes`, prefixed by any bytes held back from the previous call.
+    pub(crate) fn decode(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+
+        let mut out = String::new();
+        let mut start = 0;
+        while start < self.pending.len() {
+            match std::str::from_utf8(&self.pending[start..]) {
+                Ok(chars) => {
+                    out.push_str(chars);
+                    start = self.pending.len();
+                }
+                Err(e) => {
+                    let valid_up_to = start + e.valid_up_to();
+                    // Safe: the bytes up to `valid_up_to` were just validated.
+                    out.push_str(std::str::from_utf8(&self.pending[start..valid_up_to]).unwrap());
+                    start = valid_up_to;
+
+                    match e.error_len() {
+                        Some(len) => {
+                            render_invalid(
+                                self.policy,
+                                &self.pending[start..start + len],
+                                &mut out,
+                            );
+                            start += len;
+                        }
+                        // Incomplete sequence at the end: keep it for the next call.
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        self.pending.drain(..start);
+        out
+    }
+
+    /// Give up waiting for the rest of a held-back sequence and render it
+    /// according to the policy.
+    pub(crate) fn flush(&mut self) -> String {
+        let mut out = String::new();
+        if self.has_pending() {
+            render_invalid(self.policy, &self.pending, &mut out);
+            self.pending.clear();
+        }
+        out
+    }
+}
+
+fn render_invalid(policy: InvalidUtf8Policy, bytes: &[u8], out: &mut String) {
+    match policy {
+        InvalidUtf8Policy::Replace => out.push(char::REPLACEMENT_CHARACTER),
+        InvalidUtf8Policy::Escape => {
+            for b in bytes {
+                write!(out, "\\x{:02X}", b).unwrap();
+            }
+        }
+        InvalidUtf8Policy::Drop => {}
+    }
+}
+
+/// Character encoding of a program that does not write UTF-8.
+#[derive(Clone, Copy, Debug)]
+pub enum InputEncoding {
+    /// An encoding from the WHATWG Encoding Standard, e.g. windows-1252 or Shift_JIS.
+    Standard(&'static Encoding),
+    /// A DOS OEM code page that the Encoding Standard does not cover. `table`
+    /// maps bytes 0x80-0xFF; the lower half is ASCII.
+    Oem {
+        name: &'static str,
+        table: &'static [char; 128],
+    },
+}
+
+impl InputEncoding {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            InputEncoding::Standard(encoding) => encoding.name(),
+            InputEncoding::Oem { name, .. } => name,
+        }
+    }
+
+    /// Encode UTF-8 text for the child, substituting `?` for characters the
+    /// encoding cannot represent.
+    pub(crate) fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            InputEncoding::Standard(encoding) if *encoding == UTF_8 => text.as_bytes().to_vec(),
+            InputEncoding::Standard(encoding) => {
+                let mut out = Vec::with_capacity(text.len());
+                let mut buf = [0u8; 4];
+                for c in text.chars() {
+                    let (bytes, _, unmappable) = encoding.encode(c.encode_utf8(&mut buf));
+                    if unmappable {
+                        out.push(b'?');
+                    } else {
+                        out.extend_from_slice(&bytes);
+                    }
+                }
+                out
+            }
+            InputEncoding::Oem { table, .. } => text
+                .chars()
+                .map(|c| match c {
+                    c if c.is_ascii() => c as u8,
+                    c => table
+                        .iter()
+                        .position(|&t| t == c)
+                        .map_or(b'?', |i| 0x80 + i as u8),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for InputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let label = s.trim().to_ascii_lowercase();
+        // Accept Windows code page numbers with or without a `cp`/`ibm` prefix.
+        let code_page = label
+            .strip_prefix("cp")
+            .or_else(|| label.strip_prefix("ibm"))
+            .unwrap_or(&label);
+
+        let encoding = match code_page {
+            "437" => {
+                return Ok(InputEncoding::Oem {
+                    name: "cp437",
+                    table: &CP437,
+                });
+            }
+            "850" => {
+                return Ok(InputEncoding::Oem {
+                    name: "cp850",
+                    table: &CP850,
+                });
+            }
+            "932" => Some(SHIFT_JIS),
+            "936" => Some(GBK),
+            "949" => Some(EUC_KR),
+            "950" => Some(BIG5),
+            "65001" => Some(UTF_8),
+            _ => Encoding::for_label(label.as_bytes())
+                .or_else(|| Encoding::for_label(format!("windows-{}", code_page).as_bytes())),
+        };
+
+        encoding
+            .map(InputEncoding::Standard)
+            .ok_or_else(|| format!("unsupported encoding `{}`", s))
+    }
+}
+
+/// Turns raw pty output into the UTF-8 text stored in "o" events.
+pub(crate) enum OutputDecoder {
+    Utf8(Utf8Decoder),
+    Standard(Decoder),
+    Oem(&'static [char; 128]),
+}
+
+impl OutputDecoder {
+    pub(crate) fn new(encoding: Option<InputEncoding>, policy: InvalidUtf8Policy) -> Self {
+        match encoding {
+            None => OutputDecoder::Utf8(Utf8Decoder::new(policy)),
+            Some(InputEncoding::Standard(encoding)) if encoding == UTF_8 => {
+                OutputDecoder::Utf8(Utf8Decoder::new(policy))
+            }
+            Some(InputEncoding::Standard(encoding)) => {
+                OutputDecoder::Standard(encoding.new_decoder_without_bom_handling())
+            }
+            Some(InputEncoding::Oem { table, .. }) => OutputDecoder::Oem(table),
+        }
+    }
+
+    /// Whether an incomplete sequence is waiting for more bytes.
+    pub(crate) fn has_pending(&self) -> bool {
+        match self {
+            OutputDecoder::Utf8(decoder) => decoder.has_pending(),
+            // Legacy decoders keep split sequences internally until
+            // [`Self::flush`]; the most they could still output with no more
+            // input is only above a fresh decoder's while they do.
+            OutputDecoder::Standard(decoder) => {
+                let fresh = decoder.encoding().new_decoder_without_bom_handling();
+                decoder.max_utf8_buffer_length(0) != fresh.max_utf8_buffer_length(0)
+            }
+            OutputDecoder::Oem(_) => false,
+        }
+    }
+
+    pub(crate) fn decode(&mut self, bytes: &[u8]) -> String {
+        match self {
+            OutputDecoder::Utf8(decoder) => decoder.decode(bytes),
+            OutputDecoder::Standard(decoder) => decode_legacy(decoder, bytes, false),
+            OutputDecoder::Oem(table) => bytes
+                .iter()
+                .map(|&b| match b {
+                    0x00..=0x7F => b as char,
+                    _ => table[(b - 0x80) as usize],
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> String {
+        match self {
+            OutputDecoder::Utf8(decoder) => decoder.flush(),
+            OutputDecoder::Standard(decoder) => {
+                let out = decode_legacy(decoder, &[], true);
+                *decoder = decoder.encoding().new_decoder_without_bom_handling();
+                out
+            }
+            OutputDecoder::Oem(_) => String::new(),
+        }
+    }
+}
+
+fn decode_legacy(decoder: &mut Decoder, bytes: &[u8], last: bool) -> String {
+    // Reserving the worst case up front means a single call consumes all input.
+    let capacity = decoder
+        .max_utf8_buffer_length(bytes.len())
+        .unwrap_or(bytes.len() * 3 + 16);
+    let mut out = String::with_capacity(capacity);
+    let _ = decoder.decode_to_string(bytes, &mut out, last);
+    out
+}
+
+#[rustfmt::skip]
+static CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00A0}',
+];
+
+#[rustfmt::skip]
+static CP850: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '®', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À',
+    '©', '╣', '║', '╗', '╝', '¢', '¥', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î',
+    'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀',
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ',
+    'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{00AD}', '±', '‗', '¾', '¶', '§', '÷', '¸',
+    '°', '¨', '·', '¹', '³', '²', '■', '\u{00A0}',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{InputEncoding, InvalidUtf8Policy, OutputDecoder, Utf8Decoder};
+
+    #[test]
+    fn test_decode_split_sequence() {
+        let mut decoder = Utf8Decoder::new(InvalidUtf8Policy::Replace);
+        let bytes = "héllo".as_bytes();
+        assert_eq!(decoder.decode(&bytes[..2]), "h");
+        assert!(decoder.has_pending());
+        assert_eq!(decoder.decode(&bytes[2..]), "éllo");
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn test_decode_invalid_bytes_do_not_stall() {
+        // Latin-1 "café" followed by more output.
+        let latin1 = b"caf\xe9 ok";
+
+        let mut decoder = Utf8Decoder::new(InvalidUtf8Policy::Replace);
+        assert_eq!(decoder.decode(latin1), "caf\u{FFFD} ok");
+
+        let mut decoder = Utf8Decoder::new(InvalidUtf8Policy::Escape);
+        assert_eq!(decoder.decode(latin1), "caf\\xE9 ok");
+
+        let mut decoder = Utf8Decoder::new(InvalidUtf8Policy::Drop);
+        assert_eq!(decoder.decode(latin1), "caf ok");
+    }
+
+    #[test]
+    fn test_flush_incomplete_sequence() {
+        let mut decoder = Utf8Decoder::new(InvalidUtf8Policy::Escape);
+        assert_eq!(decoder.decode(b"caf\xe9"), "caf");
+        assert!(decoder.has_pending());
+        assert_eq!(decoder.flush(), "\\xE9");
+        assert!(!decoder.has_pending());
+        assert_eq!(decoder.flush(), "");
+    }
+
+    #[test]
+    fn test_policy_from_str() {
+        assert_eq!("replace".parse(), Ok(InvalidUtf8Policy::Replace));
+        assert_eq!("escape".parse(), Ok(InvalidUtf8Policy::Escape));
+        assert_eq!("drop".parse(), Ok(InvalidUtf8Policy::Drop));
+        assert!("ignore".parse::<InvalidUtf8Policy>().is_err());
+    }
+
+    #[test]
+    fn test_decode_oem_code_page() {
+        let encoding = "cp437".parse::<InputEncoding>().unwrap();
+        let mut decoder = OutputDecoder::new(Some(encoding), InvalidUtf8Policy::Replace);
+        assert_eq!(decoder.decode(b"\xc9\xcd\xbb caf\x82"), "╔═╗ café");
+        assert_eq!(encoding.encode("café ✓"), b"caf\x82 ?");
+    }
+
+    #[test]
+    fn test_decode_standard_encoding() {
+        let encoding = "CP1252".parse::<InputEncoding>().unwrap();
+        let mut decoder = OutputDecoder::new(Some(encoding), InvalidUtf8Policy::Replace);
+        assert_eq!(decoder.decode(b"caf\xe9 \x80"), "café €");
+        assert_eq!(encoding.encode("café €"), b"caf\xe9 \x80");
+    }
+
+    #[test]
+    fn test_decode_split_multibyte_encoding() {
+        let encoding = "shift-jis".parse::<InputEncoding>().unwrap();
+        let mut decoder = OutputDecoder::new(Some(encoding), InvalidUtf8Policy::Replace);
+        // "日本" in Shift_JIS, split in the middle of the second character.
+        assert_eq!(decoder.decode(b"\x93\xfa\x96"), "日");
+        assert!(decoder.has_pending());
+        assert_eq!(decoder.decode(b"\x7b"), "本");
+        assert!(!decoder.has_pending());
+        assert_eq!(encoding.encode("日本"), b"\x93\xfa\x96\x7b");
+    }
+
+    #[test]
+    fn test_flush_multibyte_encoding() {
+        // A lead byte ending a read is held back, then flushed when nothing
+        // follows.
+        for (label, lead) in [("cp932", 0x93), ("gbk", 0x81), ("big5", 0xa4)] {
+            let encoding = label.parse::<InputEncoding>().unwrap();
+            let mut decoder = OutputDecoder::new(Some(encoding), InvalidUtf8Policy::Replace);
+            assert_eq!(decoder.decode(&[b'a', lead]), "a");
+            assert!(decoder.has_pending(), "{}", label);
+            assert_eq!(decoder.flush(), "\u{FFFD}");
+            assert!(!decoder.has_pending(), "{}", label);
+        }
+
+        // Single-byte encodings never hold anything back.
+        let encoding = "1252".parse::<InputEncoding>().unwrap();
+        let mut decoder = OutputDecoder::new(Some(encoding), InvalidUtf8Policy::Replace);
+        assert_eq!(decoder.decode(b"caf\xe9"), "café");
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn test_input_encoding_from_str() {
+        assert!(matches!(
+            "850".parse(),
+            Ok(InputEncoding::Oem { name: "cp850", .. })
+        ));
+        assert!(
+            matches!("cp932".parse(), Ok(InputEncoding::Standard(e)) if e == encoding_rs::SHIFT_JIS)
+        );
+        assert!(
+            matches!("1251".parse(), Ok(InputEncoding::Standard(e)) if e == encoding_rs::WINDOWS_1251)
+        );
+        assert!("klingon".parse::<InputEncoding>().is_err());
+    }
+}
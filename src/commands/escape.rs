@@ -0,0 +1,157 @@
+/// Longest CSI sequence kept; anything longer is dropped.
+const MAX_CSI_LEN: usize = 64;
+
+#[derive(Default, Clone, Copy)]
+enum State {
+    #[default]
+    Text,
+    /// After ESC.
+    Escape,
+    /// After ESC and intermediate bytes, e.g. `ESC (` designating a charset.
+    EscapeIntermediate,
+    /// Inside a CSI sequence (`ESC [`), until its final byte.
+    Csi,
+    /// Inside a CSI sequence too long to keep, until its final byte.
+    CsiDiscard,
+    /// Inside an OSC, DCS, SOS, PM or APC string, until BEL or ST.
+    String,
+    /// After ESC inside a string; `\` ends it.
+    StringEscape,
+}
+
+/// A piece of terminal output.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Token {
+    /// A character outside any escape sequence, control characters included.
+    Char(char),
+    /// A complete escape sequence other than CSI, e.g. `ESC 7` or `ESC ( B`.
+    Escape(String),
+    /// A complete CSI sequence, e.g. `ESC [ 1 ; 3 2 m`.
+    Csi(String),
+    /// An OSC, DCS, SOS, PM or APC string, or a CSI sequence too long to
+    /// keep. Their content is never passed on.
+    Ignored,
+}
+
+/// Splits terminal output into characters and escape sequences. Sequences
+/// may be split across events, so the state carries over between calls.
+#[derive(Default)]
+pub(crate) struct EscapeParser {
+    state: State,
+    /// The sequence being read.
+    sequence: String,
+}
+
+impl EscapeParser {
+    /// Feeds the next character. Returns a token once one is complete.
+    pub(crate) fn feed(&mut self, c: char) -> Option<Token> {
+        let (state, token) = match self.state {
+            State::Text => self.text(c),
+            State::Escape => match c {
+                '[' => {
+                    self.sequence.push(c);
+                    (State::Csi, None)
+                }
+                ']' | 'P' | 'X' | '^' | '_' => (State::String, None),
+                '\x20'..='\x2f' => {
+                    self.sequence.push(c);
+                    (State::EscapeIntermediate, None)
+                }
+                '\x30'..='\x7e' => {
+                    self.sequence.push(c);
+                    (State::Text, Some(Token::Escape(self.take())))
+                }
+                // Not a sequence after all.
+                _ => self.text(c),
+            },
+            State::EscapeIntermediate => match c {
+                '\x20'..='\x2f' => {
+                    self.sequence.push(c);
+                    (State::EscapeIntermediate, None)
+                }
+                '\x30'..='\x7e' => {
+                    self.sequence.push(c);
+                    (State::Text, Some(Token::Escape(self.take())))
+                }
+                _ => self.text(c),
+            },
+            State::Csi => match c {
+                '\x40'..='\x7e' => {
+                    self.sequence.push(c);
+                    (State::Text, Some(Token::Csi(self.take())))
+                }
+                '\x20'..='\x3f' if self.sequence.len() < MAX_CSI_LEN => {
+                    self.sequence.push(c);
+                    (State::Csi, None)
+                }
+                '\x20'..='\x3f' => (State::CsiDiscard, None),
+                // A control character or anything else cancels the sequence.
+                _ => self.text(c),
+            },
+            State::CsiDiscard => match c {
+                '\x40'..='\x7e' => (State::Text, Some(Token::Ignored)),
+                '\x20'..='\x3f' => (State::CsiDiscard, None),
+                _ => self.text(c),
+            },
+            State::String => match c {
+                '\x07' => (State::Text, Some(Token::Ignored)),
+                '\x1b' => (State::StringEscape, None),
+                _ => (State::String, None),
+            },
+            State::StringEscape => match c {
+                '\\' => (State::Text, Some(Token::Ignored)),
+                _ => (State::String, None),
+            },
+        };
+        self.state = state;
+        token
+    }
+
+    fn text(&mut self, c: char) -> (State, Option<Token>) {
+        self.sequence.clear();
+        match c {
+            '\x1b' => {
+                self.sequence.push(c);
+                (State::Escape, None)
+            }
+            _ => (State::Text, Some(Token::Char(c))),
+        }
+    }
+
+    fn take(&mut self) -> String {
+        std::mem::take(&mut self.sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EscapeParser, Token};
+
+    fn tokens(parser: &mut EscapeParser, data: &str) -> Vec<Token> {
+        data.chars().filter_map(|c| parser.feed(c)).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut parser = EscapeParser::default();
+        assert_eq!(
+            tokens(&mut parser, "a\x1b[1;32m\x1b(B\x1b7\x1b]0;t\x07\r"),
+            vec![
+                Token::Char('a'),
+                Token::Csi("\x1b[1;32m".to_string()),
+                Token::Escape("\x1b(B".to_string()),
+                Token::Escape("\x1b7".to_string()),
+                Token::Ignored,
+                Token::Char('\r'),
+            ]
+        );
+        // Split across calls, and cancelled by a control character.
+        assert_eq!(tokens(&mut parser, "\x1b["), vec![]);
+        assert_eq!(
+            tokens(&mut parser, "2\nb"),
+            vec![Token::Char('\n'), Token::Char('b')]
+        );
+        let long = format!("\x1b[{}m", "1;".repeat(100));
+        assert_eq!(tokens(&mut parser, &long), vec![Token::Ignored]);
+    }
+}
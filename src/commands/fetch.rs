@@ -0,0 +1,317 @@
+use crate::commands::limit::format_size;
+
+use log::trace;
+use platform_dirs::AppDirs;
+use reqwest::StatusCode;
+use reqwest::header;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How recordings are downloaded.
+#[derive(Clone)]
+pub struct FetchOptions {
+    /// Time allowed for connecting and for the whole download.
+    pub timeout: Duration,
+    /// Largest recording accepted, in bytes.
+    pub max_size: u64,
+    /// Where downloads are kept for offline use; `None` disables the cache.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            timeout: Duration::from_secs(30),
+            max_size: 64 << 20,
+            cache_dir: AppDirs::new(None, true)
+                .map(|dirs| dirs.config_dir.join("PowerSession").join("cache")),
+        }
+    }
+}
+
+/// What is stored next to a cached download.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+}
+
+/// Downloads `url`, revalidating a cached copy by its ETag. If the server
+/// can't be reached or fails, a cached copy is used instead. Returns the
+/// body, or why it couldn't be had.
+pub(crate) fn fetch(url: &str, options: &FetchOptions) -> Result<Vec<u8>, String> {
+    let cache = options.cache_dir.as_ref().map(|dir| Cache::new(dir, url));
+    let cached = cache.as_ref().and_then(Cache::load);
+
+    match download(
+        url,
+        options,
+        cached.as_ref().and_then(|(entry, _)| entry.etag.as_deref()),
+    ) {
+        Ok(Download::NotModified) if cached.is_some() => {
+            trace!("{} not modified, using cached copy", url);
+            Ok(cached.unwrap().1)
+        }
+        Ok(Download::NotModified) => Err("server sent 304 for an uncached URL".to_string()),
+        Ok(Download::Body { data, etag }) => {
+            if let Some(cache) = &cache
+                && let Err(e) = cache.store(
+                    &CacheEntry {
+                        url: url.to_string(),
+                        etag,
+                    },
+                    &data,
+                )
+            {
+                trace!("failed to cache {}: {}", url, e);
+            }
+            Ok(data)
+        }
+        Err(e) => match cached {
+            Some((_, data)) => {
+                eprintln!("warning: {}; using cached copy", e);
+                Ok(data)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+enum Download {
+    NotModified,
+    Body { data: Vec<u8>, etag: Option<String> },
+}
+
+fn download(url: &str, options: &FetchOptions, etag: Option<&str>) -> Result<Download, String> {
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(options.timeout)
+        .timeout(options.timeout)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut request = client.get(url);
+    if let Some(etag) = etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    let response = request.send().map_err(|e| e.to_string())?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Download::NotModified);
+    }
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("text/html") {
+        return Err("got an HTML page instead of a recording".to_string());
+    }
+    if response
+        .content_length()
+        .is_some_and(|len| len > options.max_size)
+    {
+        return Err(too_large(options.max_size));
+    }
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    let mut data = Vec::new();
+    response
+        .take(options.max_size + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    if data.len() as u64 > options.max_size {
+        return Err(too_large(options.max_size));
+    }
+    if looks_like_html(&data) {
+        return Err("got an HTML page instead of a recording".to_string());
+    }
+
+    Ok(Download::Body { data, etag })
+}
+
+fn too_large(max_size: u64) -> String {
+    format!("recording is larger than {}", format_size(max_size))
+}
+
+/// Error pages are sometimes served without an HTML content type.
+fn looks_like_html(data: &[u8]) -> bool {
+    let start = data.trim_ascii_start();
+    let start = &start[..start.len().min(15)];
+    start.starts_with(b"<")
+        && [&b"<!doctype html"[..], b"<html"]
+            .iter()
+            .any(|tag| start.to_ascii_lowercase().starts_with(tag))
+}
+
+/// A cached download: the body in `<key>.cast` and its URL and ETag in
+/// `<key>.json`, where the key is a hash of the URL.
+struct Cache {
+    url: String,
+    data_file: PathBuf,
+    entry_file: PathBuf,
+}
+
+impl Cache {
+    fn new(dir: &std::path::Path, url: &str) -> Self {
+        let key = format!("{:016x}", fnv1a(url.as_bytes()));
+        Cache {
+            url: url.to_string(),
+            data_file: dir.join(format!("{}.cast", key)),
+            entry_file: dir.join(format!("{}.json", key)),
+        }
+    }
+
+    fn load(&self) -> Option<(CacheEntry, Vec<u8>)> {
+        let entry: CacheEntry =
+            serde_json::from_str(&fs::read_to_string(&self.entry_file).ok()?).ok()?;
+        // Different URLs may share a key.
+        if entry.url != self.url {
+            return None;
+        }
+        Some((entry, fs::read(&self.data_file).ok()?))
+    }
+
+    fn store(&self, entry: &CacheEntry, data: &[u8]) -> std::io::Result<()> {
+        if let Some(dir) = self.data_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.data_file, data)?;
+        fs::write(&self.entry_file, serde_json::to_string(entry)?)
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` stays the same across
+/// releases.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FetchOptions, fetch, looks_like_html};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Starts a stand-in HTTP server answering each request with the next of
+    /// `responses`. Returns its URL and the requests it received.
+    fn serve(responses: &'static [&'static str]) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/a/test.cast", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                requests.push(request);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn options(cache_dir: Option<PathBuf>) -> FetchOptions {
+        FetchOptions {
+            timeout: Duration::from_secs(5),
+            max_size: 1024,
+            cache_dir,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("powersession-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_fetch_rejects_html() {
+        let (url, server) = serve(&[
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 4\r\nConnection: close\r\n\r\noops",
+            "HTTP/1.1 200 OK\r\nContent-Length: 15\r\nConnection: close\r\n\r\n<!DOCTYPE html>",
+        ]);
+        assert!(fetch(&url, &options(None)).unwrap_err().contains("HTML"));
+        assert!(fetch(&url, &options(None)).unwrap_err().contains("HTML"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_fetch_max_size() {
+        let (url, server) = serve(&[
+            "HTTP/1.1 200 OK\r\nContent-Length: 2000\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n0123456789",
+        ]);
+        assert!(fetch(&url, &options(None)).unwrap_err().contains("larger"));
+        // Without a length, the download stops past the limit.
+        let mut options = options(None);
+        options.max_size = 4;
+        assert!(fetch(&url, &options).unwrap_err().contains("larger"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_fetch_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/a/test.cast", listener.local_addr().unwrap());
+        let mut options = options(None);
+        options.timeout = Duration::from_millis(200);
+        assert!(fetch(&url, &options).is_err());
+        drop(listener);
+    }
+
+    #[test]
+    fn test_fetch_cache() {
+        let dir = temp_dir("cache");
+        let (url, server) = serve(&[
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 4\r\nConnection: close\r\n\r\ncast",
+            "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let options = options(Some(dir.clone()));
+        assert_eq!(fetch(&url, &options).unwrap(), b"cast");
+        assert_eq!(fetch(&url, &options).unwrap(), b"cast");
+        assert_eq!(fetch(&url, &options).unwrap(), b"cast");
+
+        let requests = server.join().unwrap();
+        assert!(!requests[0].to_lowercase().contains("if-none-match"));
+        assert!(requests[1].to_lowercase().contains("if-none-match: \"v1\""));
+        // Offline, the cached copy is used.
+        assert_eq!(fetch(&url, &options).unwrap(), b"cast");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_looks_like_html() {
+        assert!(looks_like_html(b"\n  <!DOCTYPE html><html>"));
+        assert!(looks_like_html(b"<HTML>"));
+        assert!(!looks_like_html(b"{\"version\": 2}"));
+        assert!(!looks_like_html(b"<"));
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::Write;
+
+/// A recording's screen kept in a virtual terminal, for playing it in a
+/// terminal smaller than the one it was recorded in. Only the part of the
+/// screen that fits is drawn, scrolled so the cursor stays in view, instead
+/// of letting the host terminal wrap and scroll the output into a mess. A
+/// screen smaller than the host terminal is drawn centered in it, so it
+/// stays in place when the recording is resized.
+pub(crate) struct FitScreen {
+    parser: vt100::Parser,
+    /// Space available in the host terminal, as `(cols, rows)`.
+    view: (u16, u16),
+    /// Top-left cell of the recording's screen that is drawn.
+    top: u16,
+    left: u16,
+    /// Rows as last drawn, so only those that changed are drawn again.
+    drawn: Vec<Vec<u8>>,
+}
+
+impl FitScreen {
+    /// A screen of the recording's `size` drawn within `view`, both as
+    /// `(cols, rows)`.
+    pub(crate) fn new(size: (u16, u16), view: (u16, u16)) -> Self {
+        FitScreen {
+            parser: vt100::Parser::new(size.1.max(1), size.0.max(1), 0),
+            view: (view.0.max(1), view.1.max(1)),
+            top: 0,
+            left: 0,
+            drawn: Vec::new(),
+        }
+    }
+
+    /// Size of the area drawn in the host terminal, as `(cols, rows)`.
+    pub(crate) fn view_size(&self) -> (u16, u16) {
+        let (rows, cols) = self.parser.screen().size();
+        (cols.min(self.view.0), rows.min(self.view.1))
+    }
+
+    /// Starts over with a blank screen of `size`. The host terminal is
+    /// expected to be cleared too.
+    pub(crate) fn reset(&mut self, size: (u16, u16)) {
+        *self = FitScreen::new(size, self.view);
+    }
+
+    /// Follows a resize of the recording's screen. The host terminal is
+    /// expected to be cleared.
+    pub(crate) fn resize(&mut self, (cols, rows): (u16, u16)) {
+        self.parser.screen_mut().set_size(rows.max(1), cols.max(1));
+        self.drawn.clear();
+    }
+
+    /// Feeds the recording's output to the screen and draws what changed.
+    pub(crate) fn write(&mut self, stdout: &mut impl Write, data: &str) {
+        self.process(data);
+        self.draw(stdout);
+    }
+
+    /// Feeds the recording's output to the screen without drawing it, e.g.
+    /// while seeking.
+    pub(crate) fn process(&mut self, data: &str) {
+        self.parser.process(data.as_bytes());
+    }
+
+    /// Draws the rows that changed since the last time.
+    pub(crate) fn draw(&mut self, stdout: &mut impl Write) {
+        let (width, height) = self.view_size();
+        let (margin_top, margin_left) = ((self.view.1 - height) / 2, (self.view.0 - width) / 2);
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        let (row, col) = screen.cursor_position();
+
+        // Scroll just enough to keep the cursor in view.
+        self.top = self
+            .top
+            .clamp(row.saturating_sub(height - 1), row)
+            .min(rows - height);
+        self.left = self
+            .left
+            .clamp(col.saturating_sub(width - 1), col)
+            .min(cols - width);
+
+        let lines: Vec<Vec<u8>> = screen
+            .rows_formatted(self.left, width)
+            .skip(self.top as usize)
+            .take(height as usize)
+            .collect();
+        for (i, line) in lines.iter().enumerate() {
+            if self.drawn.get(i) != Some(line) {
+                write!(
+                    stdout,
+                    "\x1b[{};{}H\x1b[0m\x1b[2K",
+                    margin_top as usize + i + 1,
+                    margin_left + 1
+                )
+                .unwrap();
+                stdout.write_all(line).unwrap();
+            }
+        }
+        write!(
+            stdout,
+            "\x1b[0m\x1b[{};{}H\x1b[?25{}",
+            margin_top + row.saturating_sub(self.top).min(height - 1) + 1,
+            margin_left + col.saturating_sub(self.left).min(width - 1) + 1,
+            if screen.hide_cursor() { 'l' } else { 'h' }
+        )
+        .unwrap();
+        self.drawn = lines;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FitScreen;
+
+    fn draw(screen: &mut FitScreen, data: &str) -> String {
+        let mut out = Vec::new();
+        screen.write(&mut out, data);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_fit_crops_to_view() {
+        let mut screen = FitScreen::new((20, 5), (10, 3));
+        assert_eq!(screen.view_size(), (10, 3));
+
+        let out = draw(&mut screen, "0123456789abcdef\r\n");
+        assert!(out.contains("\x1b[1;1H\x1b[0m\x1b[2K0123456789"));
+        assert!(!out.contains('a'));
+        assert!(out.ends_with("\x1b[2;1H\x1b[?25h"));
+
+        // Only changed rows are drawn again.
+        let out = draw(&mut screen, "x");
+        assert!(!out.contains("\x1b[1;1H"));
+        assert!(out.contains("\x1b[2;1H\x1b[0m\x1b[2Kx"));
+    }
+
+    #[test]
+    fn test_fit_follows_cursor() {
+        let mut screen = FitScreen::new((20, 5), (10, 3));
+        draw(&mut screen, "a\r\nb\r\nc\r\nd");
+        // Rows b to d are shown, with the cursor after d.
+        let out = draw(&mut screen, "");
+        assert!(out.ends_with("\x1b[3;2H\x1b[?25h"));
+        assert_eq!(screen.top, 1);
+
+        let out = draw(&mut screen, "\x1b[1;15H");
+        assert_eq!((screen.top, screen.left), (0, 5));
+        assert!(out.ends_with("\x1b[1;10H\x1b[?25h"));
+    }
+
+    #[test]
+    fn test_fit_larger_view() {
+        let mut screen = FitScreen::new((4, 2), (80, 24));
+        assert_eq!(screen.view_size(), (4, 2));
+        screen.resize((100, 30));
+        assert_eq!(screen.view_size(), (80, 24));
+        screen.reset((4, 2));
+        assert_eq!(screen.view_size(), (4, 2));
+    }
+
+    #[test]
+    fn test_fit_centers_smaller_screen() {
+        let mut screen = FitScreen::new((4, 2), (10, 6));
+        let out = draw(&mut screen, "ab");
+        assert!(out.starts_with("\x1b[3;4H\x1b[0m\x1b[2Kab"));
+        assert!(out.ends_with("\x1b[3;6H\x1b[?25h"));
+
+        screen.resize((10, 6));
+        let out = draw(&mut screen, "");
+        assert!(out.starts_with("\x1b[1;1H\x1b[0m\x1b[2Kab"));
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::{Duration, Instant};
+
+const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+/// Parse a byte size such as `4096`, `512K`, `1.5MB` or `2G` (powers of 1024).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size `{}`, expected e.g. 512K, 100MB or 2G", s);
+
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match upper[digits.len()..]
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1u64,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+
+    match digits.trim().parse::<f64>() {
+        Ok(n) if n > 0.0 => Ok((n * unit as f64) as u64),
+        _ => Err(invalid()),
+    }
+}
+
+/// Format a byte count for humans, e.g. `1.2 GB`.
+pub(crate) fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+/// Caps how much output is written to the cast, so a runaway command can't
+/// produce an unplayable recording.
+///
+/// Once a cap is hit the output is cut off and a marker is recorded. The
+/// output left out is summarized as `[... 1.2 GB omitted ...]` when recording
+/// resumes in the next second (rate cap) or when the session ends.
+pub(crate) struct OutputLimiter {
+    max_bytes: Option<u64>,
+    max_rate: Option<u64>,
+    total: u64,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    omitted: u64,
+}
+
+impl OutputLimiter {
+    pub(crate) fn new(max_bytes: Option<u64>, max_rate: Option<u64>) -> Self {
+        OutputLimiter {
+            max_bytes,
+            max_rate,
+            total: 0,
+            window_start: None,
+            window_bytes: 0,
+            omitted: 0,
+        }
+    }
+
+    /// Returns the events to record for `chars`, received at `now`, as
+    /// `(event type, data)` pairs.
+    pub(crate) fn admit(&mut self, chars: &str, now: Instant) -> Vec<(&'static str, String)> {
+        let mut events = Vec::new();
+
+        let new_window = self
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= Duration::from_secs(1));
+        if new_window {
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+            if !self.total_reached() {
+                events.extend(self.finish());
+            }
+        }
+
+        if self.omitted > 0 {
+            self.omitted += chars.len() as u64;
+            return events;
+        }
+
+        let mut allowed = chars.len() as u64;
+        let mut cap = None;
+        if let Some(max) = self.max_bytes {
+            let left = max.saturating_sub(self.total);
+            if allowed > left {
+                allowed = left;
+                cap = Some(format!("output limit of {} reached", format_size(max)));
+            }
+        }
+        if let Some(rate) = self.max_rate {
+            let left = rate.saturating_sub(self.window_bytes);
+            if allowed > left {
+                allowed = left;
+                cap = Some(format!(
+                    "output rate limit of {}/s exceeded",
+                    format_size(rate)
+                ));
+            }
+        }
+
+        let mut end = allowed as usize;
+        while !chars.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end > 0 {
+            events.push(("o", chars[..end].to_string()));
+        }
+        self.total += end as u64;
+        self.window_bytes += end as u64;
+
+        if let Some(label) = cap {
+            events.push(("m", label));
+            self.omitted = (chars.len() - end) as u64;
+        }
+        events
+    }
+
+    /// Returns the summary of output omitted since the last cap was hit, if any.
+    pub(crate) fn finish(&mut self) -> Option<(&'static str, String)> {
+        match std::mem::take(&mut self.omitted) {
+            0 => None,
+            omitted => Some((
+                "o",
+                format!("\r\n[... {} omitted ...]\r\n", format_size(omitted)),
+            )),
+        }
+    }
+
+    fn total_reached(&self) -> bool {
+        self.max_bytes.is_some_and(|max| self.total >= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutputLimiter, format_size, parse_size};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512k"), Ok(512 * 1024));
+        assert_eq!(parse_size("1.5MB"), Ok(1536 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("10 TB").is_err());
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(1288490189), "1.2 GB");
+    }
+
+    #[test]
+    fn test_no_limits() {
+        let mut limiter = OutputLimiter::new(None, None);
+        let now = Instant::now();
+        assert_eq!(
+            limiter.admit("hello", now),
+            vec![("o", "hello".to_string())]
+        );
+        assert_eq!(limiter.finish(), None);
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let mut limiter = OutputLimiter::new(Some(8), None);
+        let now = Instant::now();
+        assert_eq!(
+            limiter.admit("hello", now),
+            vec![("o", "hello".to_string())]
+        );
+        assert_eq!(
+            limiter.admit(" world", now),
+            vec![
+                ("o", " wo".to_string()),
+                ("m", "output limit of 8 B reached".to_string())
+            ]
+        );
+        // Nothing more is recorded, even in later seconds.
+        assert!(limiter.admit("!!", now + Duration::from_secs(5)).is_empty());
+        assert_eq!(
+            limiter.finish(),
+            Some(("o", "\r\n[... 5 B omitted ...]\r\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_max_bytes_keeps_char_boundaries() {
+        let mut limiter = OutputLimiter::new(Some(2), None);
+        let events = limiter.admit("aé", Instant::now());
+        assert_eq!(events[0], ("o", "a".to_string()));
+    }
+
+    #[test]
+    fn test_max_rate() {
+        let mut limiter = OutputLimiter::new(None, Some(4));
+        let now = Instant::now();
+        assert_eq!(
+            limiter.admit("abcdef", now),
+            vec![
+                ("o", "abcd".to_string()),
+                ("m", "output rate limit of 4 B/s exceeded".to_string())
+            ]
+        );
+        assert!(
+            limiter
+                .admit("gh", now + Duration::from_millis(500))
+                .is_empty()
+        );
+        // Recording resumes in the next second, after a summary.
+        assert_eq!(
+            limiter.admit("ij", now + Duration::from_secs(1)),
+            vec![
+                ("o", "\r\n[... 4 B omitted ...]\r\n".to_string()),
+                ("o", "ij".to_string())
+            ]
+        );
+        assert_eq!(limiter.finish(), None);
+    }
+}
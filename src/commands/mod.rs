@@ -2,16 +2,33 @@ extern crate core;
 
 mod api;
 mod auth;
+mod cat;
+mod duration;
+mod encoding;
+mod escape;
+mod fetch;
+mod fit;
+mod limit;
 mod play;
 mod record;
+mod sanitize;
+mod session;
 mod stream;
 mod types;
 mod upload;
+mod watch;
 
 pub use auth::Auth;
-pub use play::Play;
-pub use record::Record;
+pub use cat::Cat;
+pub use duration::{parse_duration, parse_timestamp};
+pub use encoding::{InputEncoding, InvalidUtf8Policy};
+pub use fetch::FetchOptions;
+pub use limit::parse_size;
+pub use play::{Play, read_playlist};
+pub use record::{check_nested, Record};
+pub use session::resolve_source;
 pub use stream::Stream;
 pub use upload::Upload;
+pub use watch::{Watch, stream_ws_url};
 
 pub use api::{ApiService, Asciinema};
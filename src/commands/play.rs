@@ -1,12 +1,15 @@
-use crate::commands::types::{LineItem, RecordHeader, SessionLine, V1Recording};
+use crate::commands::fetch::FetchOptions;
+use crate::commands::fit::FitScreen;
+use crate::commands::sanitize::{EscapeFilter, plain_text};
+use crate::commands::session::{Session, is_url, start_at_from_url};
+use crate::commands::types::{SessionLine, Theme};
 
-use std::collections::HashMap;
-use std::fs::File;
+use std::borrow::Cow;
 use std::io;
-use std::io::{BufRead, Write};
-
-use std::path::Path;
+use std::io::Write;
 use std::process::exit;
+
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -14,13 +17,21 @@ use std::time::{Duration, Instant};
 #[cfg(windows)]
 use log::warn;
 
+#[cfg(windows)]
+use windows::core::w;
+
 #[cfg(windows)]
 use windows::Win32::{
-    Foundation::HANDLE,
-    Storage::FileSystem::ReadFile,
+    Foundation::{CloseHandle, GENERIC_READ, GENERIC_WRITE, HANDLE},
+    Storage::FileSystem::{
+        CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+        ReadFile,
+    },
     System::Console::{
-        GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT,
-        ENABLE_LINE_INPUT, STD_INPUT_HANDLE,
+        GetConsoleMode, GetConsoleScreenBufferInfo, GetStdHandle, SetConsoleMode,
+        CONSOLE_MODE, CONSOLE_SCREEN_BUFFER_INFO, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT,
+        ENABLE_PROCESSED_INPUT, ENABLE_VIRTUAL_TERMINAL_INPUT, STD_INPUT_HANDLE,
+        STD_OUTPUT_HANDLE,
     },
 };
 
@@ -28,6 +39,9 @@ use windows::Win32::{
 struct ConsoleGuard {
     handle: HANDLE,
     original_mode: CONSOLE_MODE,
+    /// Whether `handle` was opened by the player (`CONIN$`) and is closed
+    /// along with the guard.
+    owned: bool,
 }
 
 #[cfg(windows)]
@@ -38,645 +52,2431 @@ impl Drop for ConsoleGuard {
             if let Err(e) = SetConsoleMode(self.handle, self.original_mode) {
                 eprintln!("Warning: failed to restore console mode: {:?}", e);
             }
+            if self.owned {
+                let _ = CloseHandle(self.handle);
+            }
         }
     }
 }
 
-enum SessionLineSource {
-    Lines(io::Lines<Box<dyn BufRead>>),
-    Vec(std::vec::IntoIter<SessionLine>),
-}
+/// Reads raw bytes from the console input handle.
+#[cfg(windows)]
+struct ConsoleInput(HANDLE);
 
-struct Session {
-    #[allow(dead_code)]
-    header: RecordHeader,
-    line_iter: SessionLineSource,
+#[cfg(windows)]
+impl io::Read for ConsoleInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n_read: u32 = 0;
+        unsafe { ReadFile(self.0, Some(buf), Some(&mut n_read), None) }?;
+        Ok(n_read as usize)
+    }
 }
 
-struct StdoutIter(Session);
+const SEEK_STEP: f64 = 5.0;
+/// How often a live session checks for key commands while no events arrive.
+const LIVE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Jumping to the previous marker within this many seconds after passing a
+/// marker goes to the one before it, so repeated presses keep going back.
+const MARKER_GRACE: f64 = 1.0;
+/// Recently typed keys are cleared after this many seconds without input.
+const KEYS_TIMEOUT: f64 = 2.0;
+const MAX_KEYS_WIDTH: usize = 40;
+const MIN_SPEED: f64 = 1.0 / 16.0;
+const MAX_SPEED: f64 = 16.0;
+/// Seconds a playlist's title card is shown for.
+const TITLE_CARD_TIME: f64 = 2.0;
+
+const HELP: &str = "\
+PowerSession player\r\n\
+\r\n\
+  space    pause / resume\r\n\
+  .        step one frame while paused\r\n\
+  + / -    double / halve the speed\r\n\
+  ← / →    seek back / forward 5 seconds\r\n\
+  p / n    jump to the previous / next marker\r\n\
+  [ / ]    skip to the previous / next recording\r\n\
+  q        quit\r\n\
+  ?        show this help\r\n\
+\r\n\
+Press any key to continue.";
+
+/// Playback state shared between the timing loop and the keyboard thread.
+struct PlayerState {
+    paused: bool,
+    speed: f64,
+    /// Play the next frame right away (only while paused).
+    step: bool,
+    /// Pending relative seek in seconds.
+    seek: f64,
+    /// Pending jump to the next (1) or previous (-1) marker.
+    marker_jump: i8,
+    /// Pending skip to the next (1) or previous (-1) recording of a playlist.
+    skip: i8,
+    /// Pausing or the speed changed, so the status line is out of date.
+    changed: bool,
+    quit: bool,
+    help: bool,
+    /// Waiting for any key before going on.
+    waiting: bool,
+}
 
-impl Iterator for StdoutIter {
-    type Item = SessionLine;
+impl PlayerState {
+    fn new(speed: f64) -> Self {
+        PlayerState {
+            paused: false,
+            speed,
+            step: false,
+            seek: 0.0,
+            marker_jump: 0,
+            skip: 0,
+            changed: false,
+            quit: false,
+            help: false,
+            waiting: false,
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let event = match &mut self.0.line_iter {
-                SessionLineSource::Vec(iter) => iter.next(),
-                SessionLineSource::Lines(iter) => loop {
-                    let line = iter.next()?;
-                    let content = match line {
-                        Ok(l) => l,
-                        Err(e) => {
-                            eprintln!("error reading session data: {}", e);
-                            exit(1);
-                        }
-                    };
+    /// Whether a command is pending that the timing loop must act on.
+    fn interrupted(&self) -> bool {
+        self.step
+            || self.seek != 0.0
+            || self.marker_jump != 0
+            || self.skip != 0
+            || self.changed
+            || self.quit
+            || self.help
+    }
+}
 
-                    // Skip empty or whitespace-only lines (e.g. trailing newlines in files)
-                    if content.trim().is_empty() {
-                        continue;
-                    }
+type Controls = (Mutex<PlayerState>, Condvar);
 
-                    let line_data: Vec<LineItem> = match serde_json::from_str(&content) {
-                        Ok(data) => data,
-                        Err(e) => {
-                            eprintln!("corrupt record data: {}", e);
-                            exit(1);
-                        }
-                    };
-                    if line_data.len() != 3 {
-                        eprintln!("corrupt record: expected 3 fields, got {}", line_data.len());
-                        exit(1);
-                    }
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Char(u8),
+    Up,
+    Down,
+    Right,
+    Left,
+}
 
-                    let session_line = SessionLine {
-                        timestamp: match &line_data[0] {
-                            LineItem::F64(ts) => ts.clone(),
-                            _ => {
-                                eprintln!("corrupt record: expected timestamp as number");
-                                exit(1);
-                            }
-                        },
-                        stdout: match &line_data[1] {
-                            LineItem::String(flag) => flag == "o",
-                            _ => {
-                                eprintln!("corrupt record: expected event type as string");
-                                exit(1);
-                            }
-                        },
-                        content: match &line_data[2] {
-                            LineItem::String(line) => line.clone(),
-                            _ => {
-                                eprintln!("corrupt record: expected content as string");
-                                exit(1);
-                            }
-                        },
-                    };
-                    break Some(session_line);
-                },
-            };
+/// Turns the bytes read from the keyboard into keys, recognizing the VT
+/// sequences sent for arrow keys.
+#[derive(Default)]
+struct KeyParser {
+    buf: Vec<u8>,
+}
 
-            match event {
-                // Only yield output ("o") events; skip input ("i") and any other event types.
-                Some(line) if line.stdout => return Some(line),
-                Some(_) => continue,
-                None => return None,
+impl KeyParser {
+    fn feed(&mut self, byte: u8) -> Option<Key> {
+        self.buf.push(byte);
+        let key = match self.buf.as_slice() {
+            [0x1b] | [0x1b, b'[' | b'O'] => return None,
+            [0x1b, b'[' | b'O', rest @ ..] => {
+                // Wait for the final byte of the sequence.
+                if !(0x40..=0x7e).contains(&byte) {
+                    return None;
+                }
+                // Modifiers come as parameters, e.g. `ESC [1;5C` for Ctrl-Right.
+                match rest.last() {
+                    Some(b'A') => Some(Key::Up),
+                    Some(b'B') => Some(Key::Down),
+                    Some(b'C') => Some(Key::Right),
+                    Some(b'D') => Some(Key::Left),
+                    _ => None,
+                }
             }
-        }
+            [0x1b, b] | [b] => Some(Key::Char(*b)),
+            _ => None,
+        };
+        self.buf.clear();
+        key
     }
 }
 
-struct StdoutRelativeTimeIter(StdoutIter, f64);
-
-impl Iterator for StdoutRelativeTimeIter {
-    type Item = SessionLine;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let prev_timestamp = self.1;
+fn handle_key(state: &mut PlayerState, key: Key) {
+    // Any key dismisses the help screen.
+    if state.help {
+        state.help = false;
+        return;
+    }
+    // Any key but those that quit goes on after a wait.
+    if state.waiting {
+        state.waiting = false;
+        state.quit = matches!(key, Key::Char(b'q' | b'Q' | 0x03));
+        return;
+    }
 
-        self.0.next().map(|line| {
-            let rv = SessionLine {
-                timestamp: match prev_timestamp {
-                    x if x == 0.0 => 0.0, // first line, start right away
-                    _ => line.timestamp - prev_timestamp,
-                },
-                content: line.content,
-                stdout: line.stdout,
-            };
-            self.1 = line.timestamp;
-            rv
-        })
+    match key {
+        Key::Char(b' ') => {
+            state.paused = !state.paused;
+            state.changed = true;
+        }
+        Key::Char(b'.') if state.paused => state.step = true,
+        Key::Char(b'+' | b'=') => {
+            state.speed = (state.speed * 2.0).min(MAX_SPEED);
+            state.changed = true;
+        }
+        Key::Char(b'-') => {
+            state.speed = (state.speed / 2.0).max(MIN_SPEED);
+            state.changed = true;
+        }
+        Key::Right => state.seek += SEEK_STEP,
+        Key::Left => state.seek -= SEEK_STEP,
+        Key::Char(b'n') => state.marker_jump = 1,
+        Key::Char(b'p') => state.marker_jump = -1,
+        Key::Char(b']') => state.skip = 1,
+        Key::Char(b'[') => state.skip = -1,
+        Key::Char(b'q' | b'Q' | 0x03) => state.quit = true,
+        Key::Char(b'?') => state.help = true,
+        _ => {}
     }
 }
 
-fn is_url(input: &str) -> bool {
-    input.starts_with("http://") || input.starts_with("https://")
+/// Reads keys from `input` until it closes and applies them to `controls`.
+fn read_keys(mut input: impl io::Read, controls: &Controls) {
+    let mut parser = KeyParser::default();
+    let mut buf = [0u8; 16];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(n) if n > 0 => n,
+            _ => break,
+        };
+        for &byte in &buf[..n] {
+            if let Some(key) = parser.feed(byte) {
+                let (lock, cvar) = controls;
+                handle_key(&mut lock.lock().unwrap(), key);
+                cvar.notify_all();
+            }
+        }
+    }
 }
 
-/// Normalize an asciinema.org recording URL to its raw `.cast` download URL.
-/// For example, `https://asciinema.org/a/abc123` becomes
-/// `https://asciinema.org/a/abc123.cast`.
-/// Query strings and fragments are preserved (e.g. `?t=10` stays after `.cast`).
-fn normalize_url(url: &str) -> String {
-    // Split off fragment, preserving the leading '#'
-    let (before_fragment, fragment) = match url.find('#') {
-        Some(idx) => (&url[..idx], &url[idx..]),
-        None => (url, ""),
-    };
-
-    // Split off query, preserving the leading '?'
-    let (mut main, query) = match before_fragment.find('?') {
-        Some(idx) => (&before_fragment[..idx], &before_fragment[idx..]),
-        None => (before_fragment, ""),
-    };
+/// Maps recording time to wall clock time. Events are due at a fixed offset
+/// from where the timeline was last set, so time spent writing output doesn't
+/// add up to drift over a long recording. The timeline is only reset when
+/// playback is paused, seeks or changes speed.
+struct Timeline {
+    origin: Instant,
+    /// Recording time at `origin`.
+    position: f64,
+    speed: f64,
+    paused: bool,
+    /// Events are due right away; only the recording time advances.
+    instant: bool,
+}
 
-    // Only normalize asciinema recording URLs
-    if main.contains("asciinema.org/a/") {
-        // Remove a trailing slash from the path, if present
-        if main.ends_with('/') {
-            main = &main[..main.len() - 1];
+impl Timeline {
+    fn new(speed: f64) -> Self {
+        Timeline {
+            origin: Instant::now(),
+            position: 0.0,
+            speed,
+            paused: false,
+            instant: false,
         }
+    }
 
-        let mut normalized = main.to_string();
-        if !normalized.ends_with(".cast") {
-            normalized.push_str(".cast");
+    /// The current recording time.
+    fn now(&self) -> f64 {
+        if self.paused || self.instant {
+            self.position
+        } else {
+            self.position + self.origin.elapsed().as_secs_f64() * self.speed
         }
+    }
 
-        // Reattach query and fragment in their original order
-        normalized.push_str(query);
-        normalized.push_str(fragment);
-        normalized
-    } else {
-        // Non-asciinema URLs are returned unchanged
-        url.to_string()
+    /// Continues from `position`, e.g. after a seek.
+    fn seek(&mut self, position: f64) {
+        self.origin = Instant::now();
+        self.position = position;
     }
-}
 
-/// Parse a session from a buffered reader, detecting v2 or v1 format automatically.
-/// The `source_name` is used only in error messages.
-fn parse_reader(reader: Box<dyn BufRead>, source_name: &str) -> Session {
-    let mut line_iter: io::Lines<Box<dyn BufRead>> = reader.lines();
+    fn pause(&mut self) {
+        self.position = self.now();
+        self.paused = true;
+    }
 
-    let first_line = match line_iter.next() {
-        Some(Ok(line)) => line,
-        Some(Err(e)) => {
-            eprintln!("error reading '{}': {}", source_name, e);
-            exit(1);
-        }
-        None => {
-            eprintln!("'{}': file is empty", source_name);
-            exit(1);
-        }
-    };
+    /// Continues from the current recording time at `speed`.
+    fn resume(&mut self, speed: f64) {
+        self.position = self.now();
+        self.origin = Instant::now();
+        self.speed = speed;
+        self.paused = false;
+    }
 
-    if let Ok(header) = serde_json::from_str::<RecordHeader>(&first_line) {
-        // v2 format: header on first line, events stream on subsequent lines.
-        // Validate version == 2 to avoid misclassifying v1 recordings that
-        // happen to contain a timestamp field parseable as RecordHeader.
-        if header.version == 2 {
-            Session {
-                header,
-                line_iter: SessionLineSource::Lines(line_iter),
-            }
-        } else {
-            // Not v2 — fall through and try v1 parsing with the full content.
-            let mut file_content = first_line;
-            for line in line_iter {
-                file_content.push('\n');
-                match line {
-                    Ok(l) => file_content.push_str(&l),
-                    Err(e) => {
-                        eprintln!("error reading '{}': {}", source_name, e);
-                        exit(1);
-                    }
-                }
-            }
-            parse_v1(source_name, file_content)
-        }
-    } else {
-        // Try v1 format: entire content is a single JSON object.
-        // Collect remaining lines from the already-opened iterator.
-        let mut file_content = first_line;
-        for line in line_iter {
-            file_content.push('\n');
-            match line {
-                Ok(l) => file_content.push_str(&l),
-                Err(e) => {
-                    eprintln!("error reading '{}': {}", source_name, e);
-                    exit(1);
-                }
-            }
-        }
-        parse_v1(source_name, file_content)
+    /// The wall clock time at which the recording reaches `position`.
+    fn deadline(&self, position: f64) -> Instant {
+        self.origin + Duration::from_secs_f64(((position - self.position) / self.speed).max(0.0))
     }
 }
 
-fn parse_v1(source_name: &str, file_content: String) -> Session {
-    match serde_json::from_str::<V1Recording>(&file_content) {
-        Ok(recording) if recording.version == 1 => {
-            let header = RecordHeader {
-                version: recording.version,
-                width: recording.width,
-                height: recording.height,
-                timestamp: 0,
-                environment: HashMap::new(),
-            };
-
-            let mut absolute_time: f64 = 0.0;
-            let events: Vec<SessionLine> = recording
-                .stdout
-                .into_iter()
-                .map(|(delay, text)| {
-                    absolute_time += delay;
-                    SessionLine {
-                        timestamp: absolute_time,
-                        stdout: true,
-                        content: text,
-                    }
-                })
-                .collect();
+/// Waits until the recording reaches `target` on the timeline, following
+/// pauses and speed changes. Returns `false` if a key command interrupted the
+/// wait.
+fn wait_until(controls: &Controls, timeline: &mut Timeline, target: f64) -> bool {
+    let (lock, cvar) = controls;
+    let mut state = lock.lock().unwrap();
 
-            Session {
-                header,
-                line_iter: SessionLineSource::Vec(events.into_iter()),
+    loop {
+        if state.interrupted() {
+            return false;
+        }
+        if state.paused {
+            if !timeline.paused {
+                timeline.pause();
             }
+            state = cvar.wait(state).unwrap();
+            continue;
         }
-        Ok(recording) => {
-            eprintln!(
-                "'{}': unsupported file format version {}",
-                source_name, recording.version
-            );
-            exit(1);
+        if timeline.paused || timeline.speed != state.speed {
+            timeline.resume(state.speed);
         }
-        Err(e) => {
-            eprintln!(
-                "'{}': unsupported or corrupt session file: {}",
-                source_name, e
-            );
-            exit(1);
+        if timeline.instant {
+            timeline.position = timeline.position.max(target);
+            return true;
+        }
+
+        // Wait for the deadline; an early wakeup means a key was pressed.
+        let now = Instant::now();
+        let deadline = timeline.deadline(target);
+        if deadline <= now {
+            return true;
         }
+        state = cvar.wait_timeout(state, deadline - now).unwrap().0;
     }
 }
 
-impl Session {
-    fn new(source: &str) -> Self {
-        if is_url(source) { Self::from_url(source) } else { Self::from_file(source) }
+pub struct Play {
+    /// The recordings to play in turn, unless a session was given.
+    sources: Vec<String>,
+    session: Option<Session>,
+    fetch: FetchOptions,
+    idle_time_limit: Option<f64>,
+    speed: f64,
+    start_at: Option<f64>,
+    pause_on_markers: bool,
+    /// Number of times to play the recordings; `None` loops forever.
+    loops: Option<u64>,
+    loop_gap: f64,
+    /// A recording is read from stdin, so keys must come from elsewhere.
+    from_stdin: bool,
+    show_input: bool,
+    /// Ask the host terminal to match the recording's size.
+    resize: bool,
+    /// Crop the recording to the host terminal when it doesn't fit.
+    fit: bool,
+    /// Skip corrupt event lines instead of stopping at the first one.
+    lenient: bool,
+    /// Where output goes instead of stdout.
+    output: Option<Box<dyn Write>>,
+    /// Don't wait between events.
+    instant: bool,
+    /// Pass remote recordings' escape sequences on unfiltered.
+    unsafe_raw: bool,
+    /// Show the title of each recording of a playlist before playing it.
+    title_cards: bool,
+    /// Show the position, speed and marker in a status line on the
+    /// terminal's last row.
+    status: bool,
+}
+
+/// How playing a recording ended.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Finished,
+    /// Skip to the next (1) or previous (-1) recording.
+    Skip(i8),
+    Quit,
+}
+
+/// A recording ready to be played, kept so that seeking backwards and looping
+/// can replay it without reading it again.
+struct Recording {
+    /// The file name or URL it was read from.
+    source: String,
+    size: (u16, u16),
+    title: Option<String>,
+    theme: Option<String>,
+    /// Events with absolute timestamps, with the idle time limit applied.
+    frames: Vec<SessionLine>,
+    duration: f64,
+}
+
+impl Play {
+    /// Plays `sources` one after the other.
+    pub fn new(sources: Vec<String>, idle_time_limit: Option<f64>, speed: f64) -> Self {
+        Play {
+            start_at: match sources.first() {
+                Some(first) if is_url(first) => start_at_from_url(first),
+                _ => None,
+            },
+            session: None,
+            fetch: FetchOptions::default(),
+            idle_time_limit,
+            speed,
+            pause_on_markers: false,
+            loops: Some(1),
+            loop_gap: 0.0,
+            from_stdin: sources.iter().any(|source| source == "-"),
+            show_input: false,
+            resize: false,
+            fit: false,
+            lenient: false,
+            output: None,
+            instant: false,
+            unsafe_raw: false,
+            title_cards: false,
+            status: false,
+            sources,
+        }
     }
 
-    fn from_file(filename: &str) -> Self {
-        if !Path::new(filename).exists() {
-            eprintln!("session with name {} does not exist", filename);
-            exit(1);
+    /// Plays a live session as its events arrive.
+    pub(crate) fn live(session: Session) -> Self {
+        Play {
+            session: Some(session),
+            ..Self::new(Vec::new(), None, 1.0)
         }
+    }
 
-        let file = match File::open(filename) {
-            Ok(f) => f,
-            Err(e) => {
-                eprintln!("error opening '{}': {}", filename, e);
-                exit(1);
-            }
-        };
-        parse_reader(Box::new(io::BufReader::new(file)), filename)
+    /// Start playback at `start_at` seconds into the recording instead of the
+    /// position given by a URL's `t` parameter, if any.
+    pub fn with_start_at(mut self, start_at: Option<f64>) -> Self {
+        if start_at.is_some() {
+            self.start_at = start_at;
+        }
+        self
     }
 
-    fn from_url(url: &str) -> Self {
-        let url = normalize_url(url);
-        let response = reqwest::blocking::get(&url).unwrap_or_else(|e| {
-            eprintln!("failed to fetch URL {}: {}", url, e);
-            exit(1);
-        });
+    /// Pause playback whenever a marker is reached.
+    pub fn with_pause_on_markers(mut self, pause_on_markers: bool) -> Self {
+        self.pause_on_markers = pause_on_markers;
+        self
+    }
 
-        if !response.status().is_success() {
-            eprintln!("failed to fetch URL {}: HTTP {}", url, response.status());
-            exit(1);
-        }
+    /// Play the recordings `loops` times, or forever if `None`, waiting
+    /// `gap` seconds between iterations.
+    pub fn with_loop(mut self, loops: Option<u64>, gap: f64) -> Self {
+        self.loops = loops;
+        self.loop_gap = gap;
+        self
+    }
 
-        parse_reader(Box::new(io::BufReader::new(response)), &url)
+    /// Show the keys typed during recording ("i" events) in a status line.
+    pub fn with_show_input(mut self, show_input: bool) -> Self {
+        self.show_input = show_input;
+        self
     }
 
-    fn stdout_iter(self) -> StdoutIter {
-        StdoutIter(self)
+    /// Ask the host terminal to resize to the recording's size (XTWINOPS)
+    /// instead of clearing the screen when the recording was resized, and
+    /// back to its own size when playback ends.
+    pub fn with_resize(mut self, resize: bool) -> Self {
+        self.resize = resize;
+        self
     }
 
-    fn stdout_relative_time_iter(self) -> StdoutRelativeTimeIter {
-        StdoutRelativeTimeIter(self.stdout_iter(), 0.0)
+    /// Play recordings larger than the host terminal through a virtual
+    /// screen, drawing only the part that fits around the cursor, instead of
+    /// letting the output wrap.
+    pub fn with_fit(mut self, fit: bool) -> Self {
+        self.fit = fit;
+        self
     }
-}
 
-/// Waits for `delay` seconds, respecting pause/resume signals from `pair`.
-/// Returns once the full delay has elapsed (accounting for time spent paused).
-fn wait_interruptible(pair: &Arc<(Mutex<bool>, Condvar)>, delay: f64) {
-    let mut remaining = delay;
-    while remaining > 1e-9 {
-        let (lock, cvar) = &**pair;
-        let mut paused_guard = lock.lock().unwrap();
+    /// Warn about corrupt event lines and skip them rather than exiting.
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
 
-        // Block while paused.
-        while *paused_guard {
-            paused_guard = cvar.wait(paused_guard).unwrap();
-        }
+    /// Download remote recordings with `fetch`.
+    pub fn with_fetch_options(mut self, fetch: FetchOptions) -> Self {
+        self.fetch = fetch;
+        self
+    }
 
-        // Wait for the remaining delay; an early wakeup means pause was toggled.
-        let start = Instant::now();
-        let (_guard, timed_out) = cvar
-            .wait_timeout(paused_guard, Duration::from_secs_f64(remaining))
-            .unwrap();
+    /// Pass a remote recording's output to the terminal unfiltered.
+    pub fn with_unsafe_raw(mut self, unsafe_raw: bool) -> Self {
+        self.unsafe_raw = unsafe_raw;
+        self
+    }
 
-        let elapsed = start.elapsed().as_secs_f64();
-        remaining = (remaining - elapsed).max(0.0);
+    /// Show a title card with the name of each recording before playing it.
+    pub fn with_title_cards(mut self, title_cards: bool) -> Self {
+        self.title_cards = title_cards;
+        self
+    }
 
-        if timed_out.timed_out() {
-            break;
-        }
-        // Woken early because pause was toggled — loop back to re-check
-        // the paused state and continue with the remaining delay.
+    /// Show a status line with the position in the recording, its length,
+    /// the speed and the last marker passed.
+    pub fn with_status(mut self, status: bool) -> Self {
+        self.status = status;
+        self
     }
-}
 
-pub struct Play {
-    session: Session,
-    idle_time_limit: Option<f64>,
-    speed: f64,
-}
+    /// Write the recording's output to `output` instead of stdout.
+    #[cfg(test)]
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
 
-impl Play {
-    pub fn new(filename: String, idle_time_limit: Option<f64>, speed: f64) -> Self {
-        Play {
-            session: Session::new(&filename),
-            idle_time_limit,
-            speed,
-        }
+    /// Write events as fast as possible instead of at their recorded times.
+    /// Pauses, seeking and loops still follow the recording's timeline.
+    #[cfg(test)]
+    pub fn with_instant(mut self, instant: bool) -> Self {
+        self.instant = instant;
+        self
     }
 
-    pub fn execute(self) {
-        // Shared pause state: (paused flag, condvar to signal changes)
-        let pair = Arc::new((Mutex::new(false), Condvar::new()));
-        let pair_clone = Arc::clone(&pair);
+    pub fn execute(mut self) {
+        let controls = Arc::new((Mutex::new(PlayerState::new(self.speed)), Condvar::new()));
+        let controls_clone = Arc::clone(&controls);
 
         // On Windows: save stdin console mode and switch to raw (no line
-        // buffering / no echo) so space arrives immediately without Enter.
+        // buffering / no echo) so keys arrive immediately without Enter. VT
+        // input makes arrow keys arrive as escape sequences, and with processed
+        // input off Ctrl-C arrives as a key instead of killing the player.
         // We use an RAII guard to ensure console mode is restored on all exit paths.
+        // When the recording is read from stdin, keys come from the console
+        // itself instead.
+        #[cfg(windows)]
+        let input = unsafe {
+            if self.from_stdin {
+                CreateFileW(
+                    w!("CONIN$"),
+                    (GENERIC_READ | GENERIC_WRITE).0,
+                    FILE_SHARE_READ | FILE_SHARE_WRITE,
+                    None,
+                    OPEN_EXISTING,
+                    FILE_FLAGS_AND_ATTRIBUTES::default(),
+                    None,
+                )
+            } else {
+                GetStdHandle(STD_INPUT_HANDLE)
+            }
+        };
         #[cfg(windows)]
         let _console_guard: Option<ConsoleGuard> = unsafe {
-            match GetStdHandle(STD_INPUT_HANDLE) {
+            match input {
                 Ok(h) if !h.is_invalid() => {
                     let mut orig = CONSOLE_MODE::default();
                     if GetConsoleMode(h, &mut orig).is_err() {
+                        if self.from_stdin {
+                            let _ = CloseHandle(h);
+                        }
                         warn!(
-                            "pause: stdin is not a console (redirected?); \
-                             space-to-pause is disabled"
+                            "play: stdin is not a console (redirected?); \
+                             keyboard controls are disabled"
                         );
                         None
                     } else {
                         let mut raw = orig;
                         raw &= !ENABLE_LINE_INPUT;
                         raw &= !ENABLE_ECHO_INPUT;
+                        raw &= !ENABLE_PROCESSED_INPUT;
+                        raw |= ENABLE_VIRTUAL_TERMINAL_INPUT;
                         if let Err(e) = SetConsoleMode(h, raw) {
-                            warn!("pause: failed to set console mode: {:?}; space-to-pause is disabled", e);
+                            if self.from_stdin {
+                                let _ = CloseHandle(h);
+                            }
+                            warn!("play: failed to set console mode: {:?}; keyboard controls are disabled", e);
                             None
                         } else {
                             Some(ConsoleGuard {
                                 handle: h,
                                 original_mode: orig,
+                                owned: self.from_stdin,
                             })
                         }
                     }
                 }
                 _ => {
                     warn!(
-                        "pause: could not obtain a valid stdin handle; \
-                         space-to-pause is disabled"
+                        "play: could not obtain a valid console input handle; \
+                         keyboard controls are disabled"
                     );
                     None
                 }
             }
         };
 
-        // Spawn a thread that reads stdin and toggles pause when space is pressed.
-        // Only spawn the thread when pause support is actually enabled.
+        // Spawn a thread that reads keys and updates the player state.
+        // Only spawn the thread when keyboard controls are actually enabled.
         #[cfg(windows)]
-        if _console_guard.is_some() {
-            thread::spawn(move || {
-                unsafe {
-                    let stdin_handle = match GetStdHandle(STD_INPUT_HANDLE) {
-                        Ok(h) if !h.is_invalid() => h,
-                        _ => return,
-                    };
-                    loop {
-                        let mut buf = [0u8; 1];
-                        let mut n_read: u32 = 0;
-                        if ReadFile(stdin_handle, Some(&mut buf), Some(&mut n_read), None).is_err()
-                            || n_read == 0
-                        {
-                            break;
-                        }
-                        if buf[0] == b' ' {
-                            let (lock, cvar) = &*pair_clone;
-                            let mut paused = lock.lock().unwrap();
-                            *paused = !*paused;
-                            cvar.notify_all();
-                        }
-                    }
-                }
-            });
+        let interactive = _console_guard.is_some();
+        #[cfg(windows)]
+        if let Some(guard) = &_console_guard {
+            // Handles aren't Send; pass the raw value to the thread.
+            let handle = guard.handle.0 as isize;
+            thread::spawn(move || read_keys(ConsoleInput(HANDLE(handle as _)), &controls_clone));
         }
 
+        // Only enable keyboard controls when stdin is an interactive
+        // terminal; don't consume piped input in tests or scripted environments.
+        // When the recording is read from stdin, keys come from the terminal
+        // device instead.
         #[cfg(not(windows))]
-        {
+        let keys: Option<Box<dyn io::Read + Send>> = {
             use std::io::IsTerminal;
-            // Only enable pause when stdin is an interactive terminal; don't
-            // consume piped input in tests or scripted environments.
-            if std::io::stdin().is_terminal() {
-                thread::spawn(move || {
-                    use std::io::Read;
-                    loop {
-                        let mut buf = [0u8; 1];
-                        match std::io::stdin().lock().read(&mut buf) {
-                            Ok(1) if buf[0] == b' ' => {
-                                let (lock, cvar) = &*pair_clone;
-                                let mut paused = lock.lock().unwrap();
-                                *paused = !*paused;
-                                cvar.notify_all();
+            if self.from_stdin {
+                std::fs::File::open("/dev/tty").ok().map(|tty| Box::new(tty) as _)
+            } else if std::io::stdin().is_terminal() {
+                Some(Box::new(std::io::stdin()))
+            } else {
+                None
+            }
+        };
+        #[cfg(not(windows))]
+        let interactive = keys.is_some();
+        #[cfg(not(windows))]
+        if let Some(keys) = keys {
+            thread::spawn(move || read_keys(keys, &controls_clone));
+        }
+
+        let pause_on_markers = self.pause_on_markers && interactive;
+        let to_terminal = self.output.is_none();
+        let mut stdout = self.output.take().unwrap_or_else(|| Box::new(io::stdout()));
+        let host_size = if to_terminal {
+            host_terminal_size()
+        } else {
+            None
+        };
+
+        if let Some(session) = self.session.take() {
+            self.play_live(session, &mut stdout, &controls, pause_on_markers, host_size);
+            self.restore_size(&mut stdout, host_size);
+            return;
+        }
+
+        let mut recordings: Vec<Option<Recording>> = self.sources.iter().map(|_| None).collect();
+        let mut start_at = self.start_at;
+        let mut item = 0;
+        let mut iteration = 0;
+        while item < self.sources.len() {
+            if recordings[item].is_none() {
+                match self.load(&self.sources[item]) {
+                    Ok(recording) => {
+                        if let Some(host) = host_size
+                            && !self.resize
+                            && !self.fit
+                            && let Some(warning) = size_warning(
+                                host,
+                                largest_size(recording.size, &recording.frames),
+                                self.status,
+                            )
+                        {
+                            eprintln!("{}", warning);
+                            if interactive {
+                                eprint!("press any key to play anyway, or q to quit");
+                                let go_on = wait_for_key(&controls);
+                                eprintln!();
+                                if !go_on {
+                                    break;
+                                }
                             }
-                            Ok(n) if n > 0 => {}
-                            _ => break,
                         }
+                        recordings[item] = Some(recording);
+                    }
+                    Err(e) => {
+                        // exit() skips destructors; restore the console first.
+                        self.restore_size(&mut stdout, host_size);
+                        #[cfg(windows)]
+                        drop(_console_guard);
+                        eprintln!("{}", e);
+                        exit(1);
                     }
-                });
+                }
             }
-        }
+            let recording = recordings[item].as_ref().unwrap();
 
-        for stdout_item in self.session.stdout_relative_time_iter() {
-            let mut delay = stdout_item.timestamp;
-            if let Some(limit) = self.idle_time_limit {
-                delay = delay.min(limit);
+            let mut outcome = Outcome::Finished;
+            if self.title_cards && self.sources.len() > 1 {
+                outcome = self.title_card(&mut stdout, &controls, item, recording);
+            }
+            if outcome == Outcome::Finished {
+                if iteration == 0 {
+                    // On stderr, so the output is only the recording's.
+                    let (w, h) = recording.size;
+                    match &recording.title {
+                        Some(title) => eprintln!("{} ({}x{})", title, w, h),
+                        None => eprintln!("Recorded at {}x{}", w, h),
+                    }
+                }
+                outcome = self.play_recording(
+                    recording,
+                    &mut stdout,
+                    &controls,
+                    pause_on_markers,
+                    start_at.take(),
+                    host_size,
+                );
+                if self.status {
+                    // Give the whole screen back.
+                    stdout.write_all(b"\x1b7\x1b[r\x1b8").unwrap();
+                }
+                if recording.theme.is_some() {
+                    // Back to the terminal's own colors.
+                    stdout
+                        .write_all(b"\x1b]110\x1b\\\x1b]111\x1b\\\x1b]104\x1b\\")
+                        .unwrap();
+                    stdout.flush().unwrap();
+                }
             }
-            delay /= self.speed;
-
-            wait_interruptible(&pair, delay);
 
-            io::stdout()
-                .write_all(stdout_item.content.as_bytes())
-                .unwrap();
-            io::stdout().flush().unwrap();
+            match outcome {
+                Outcome::Quit => break,
+                Outcome::Skip(-1) => item = item.saturating_sub(1),
+                Outcome::Finished | Outcome::Skip(_) => {
+                    item += 1;
+                    if item == self.sources.len() {
+                        iteration += 1;
+                        if self.loops.is_some_and(|loops| iteration >= loops) {
+                            break;
+                        }
+                        // Wait between iterations (in real time, whatever the
+                        // speed), then start over.
+                        let speed = controls.0.lock().unwrap().speed;
+                        self.wait(&controls, self.loop_gap * speed);
+                        item = 0;
+                    }
+                }
+            }
+            // The next recording starts on a clean screen.
+            stdout.write_all(b"\x1bc").unwrap();
         }
+        self.restore_size(&mut stdout, host_size);
         // Console mode is automatically restored by the ConsoleGuard's Drop impl
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::{is_url, normalize_url, wait_interruptible};
-    use crate::Play;
-    use std::path::PathBuf;
-    use std::sync::{Arc, Condvar, Mutex};
-    use std::thread;
-    use std::time::{Duration, Instant};
 
-    fn test_data_path() -> String {
-        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        d.push("testdata/play.txt");
-        d.as_path().to_str().unwrap().to_owned()
+    /// With `--resize`, asks the host terminal to go back to the size it had
+    /// before playback.
+    fn restore_size(&self, stdout: &mut impl Write, host_size: Option<(u16, u16)>) {
+        if let Some(size) = host_size.filter(|_| self.resize) {
+            request_size(stdout, size);
+            stdout.flush().unwrap();
+        }
     }
 
-    fn test_data_v1_path() -> String {
-        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        d.push("testdata/play_v1.json");
-        d.as_path().to_str().unwrap().to_owned()
+    /// Waits for `delay` seconds of recording time, unless a key interrupts it.
+    fn wait(&self, controls: &Controls, delay: f64) {
+        let speed = controls.0.lock().unwrap().speed;
+        let mut timeline = Timeline {
+            instant: self.instant,
+            ..Timeline::new(speed)
+        };
+        wait_until(controls, &mut timeline, delay);
     }
 
-    fn test_data_with_stdin_path() -> String {
-        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        d.push("testdata/play_with_stdin.txt");
-        d.as_path().to_str().unwrap().to_owned()
-    }
+    /// Reads a recording, printing warnings about skipped lines in lenient
+    /// mode. Returns the error message if it can't be read.
+    fn load(&self, source: &str) -> Result<Recording, String> {
+        let session =
+            Session::new(source, &self.fetch).map_err(|e| format!("'{}': {}", source, e))?;
+        let header = &session.header;
+        let size = (header.width as u16, header.height as u16);
+        let title = header.title.as_deref().map(plain_text);
+        // A recording's theme only applies if its colors are well-formed.
+        let theme = header.theme.as_ref().and_then(theme_sequence);
+        let idle_time_limit = self.idle_time_limit.or(header.idle_time_limit);
+        let mut filter = (is_url(source) && !self.unsafe_raw).then(EscapeFilter::default);
+
+        // Positions follow the recorded times, with the idle time limit
+        // applied to the gaps between events.
+        let mut frames = Vec::new();
+        let mut at = 0.0;
+        let mut recorded_at = 0.0;
+        for event in session.event_iter(self.codes()) {
+            let mut event = match event {
+                Ok(event) => event,
+                Err(e) if self.lenient && e.is_skippable() => {
+                    eprintln!("warning: skipping {}", e);
+                    continue;
+                }
+                Err(e) => return Err(format!("'{}': {}", source, e)),
+            };
+            let mut delay = (event.timestamp - recorded_at).max(0.0);
+            recorded_at = event.timestamp;
+            if let Some(limit) = idle_time_limit {
+                delay = delay.min(limit);
+            }
+            at += delay;
+            event.timestamp = at;
+            sanitize_frame(&mut filter, &mut event);
+            frames.push(event);
+        }
 
-    #[test]
-    fn test_play() {
-        let play = Play::new(test_data_path(), None, 1.0);
-        play.execute();
+        Ok(Recording {
+            source: source.to_string(),
+            size,
+            title,
+            theme,
+            frames,
+            duration: at,
+        })
     }
 
-    #[test]
-    fn test_play_with_speed() {
-        let play = Play::new(test_data_path(), None, 2.0);
-        play.execute();
+    /// The event types played.
+    fn codes(&self) -> &'static [&'static str] {
+        if self.show_input {
+            &["o", "m", "r", "i"]
+        } else {
+            &["o", "m", "r"]
+        }
     }
 
-    #[test]
-    fn test_play_with_idle_time_limit() {
-        let play = Play::new(test_data_path(), Some(0.5), 1.0);
-        play.execute();
+    /// Shows which recording of the playlist comes next, for a couple of
+    /// seconds or until a key is pressed.
+    fn title_card(
+        &self,
+        stdout: &mut impl Write,
+        controls: &Controls,
+        item: usize,
+        recording: &Recording,
+    ) -> Outcome {
+        let name = recording.title.as_deref().unwrap_or(&recording.source);
+        write!(
+            stdout,
+            "\x1b[H\x1b[2J\r\n  \x1b[1m{}/{}\x1b[0m  {}\r\n",
+            item + 1,
+            self.sources.len(),
+            name
+        )
+        .unwrap();
+        stdout.flush().unwrap();
+
+        let speed = controls.0.lock().unwrap().speed;
+        self.wait(controls, TITLE_CARD_TIME * speed);
+
+        let mut state = controls.0.lock().unwrap();
+        let outcome = match std::mem::take(&mut state.skip) {
+            _ if state.quit => Outcome::Quit,
+            0 => Outcome::Finished,
+            direction => Outcome::Skip(direction),
+        };
+        // Other keys just end the card.
+        state.step = false;
+        state.seek = 0.0;
+        state.marker_jump = 0;
+        drop(state);
+
+        stdout.write_all(b"\x1b[H\x1b[2J").unwrap();
+        outcome
     }
 
-    #[test]
-    fn test_play_with_speed_and_idle_time_limit() {
-        let play = Play::new(test_data_path(), Some(0.5), 2.0);
-        play.execute();
+    /// Returns the virtual screen to play through, if any: with `--fit` if
+    /// the recording, at its `largest` size, doesn't fit in the host terminal
+    /// less the status line, and without `--resize` if it fits but `resizes`,
+    /// so that it stays centered instead of moving about.
+    fn fit_screen(
+        &self,
+        size: (u16, u16),
+        (width, height): (u16, u16),
+        resizes: bool,
+        host_size: Option<(u16, u16)>,
+    ) -> Option<FitScreen> {
+        let (cols, rows) = host_size.filter(|_| !self.resize)?;
+        let rows = rows.saturating_sub(self.status as u16);
+        let fits = width <= cols && height <= rows;
+        (if fits { resizes } else { self.fit }).then(|| FitScreen::new(size, (cols, rows)))
     }
 
-    #[test]
-    fn test_play_v1_format() {
-        let play = Play::new(test_data_v1_path(), None, 1.0);
-        play.execute();
+    /// Plays a live session, rendering events as they arrive.
+    fn play_live(
+        &self,
+        session: Session,
+        stdout: &mut impl Write,
+        controls: &Controls,
+        pause_on_markers: bool,
+        host_size: Option<(u16, u16)>,
+    ) {
+        let header_size = (session.header.width as u16, session.header.height as u16);
+        let mut status = StatusLine::new(host_size.map(|(_, rows)| rows));
+        let codes = self.codes();
+        let mut filter = (!self.unsafe_raw).then(EscapeFilter::default);
+        let events = session.into_live().unwrap();
+        // A live session may be resized at any time.
+        let mut fit = self.fit_screen(header_size, header_size, true, host_size);
+        reset_size(stdout, &mut status, &mut fit, header_size, self.resize);
+
+        // Events are rendered as they arrive; while paused they queue up.
+        loop {
+            let (lock, cvar) = controls;
+            let state = lock.lock().unwrap();
+            if state.quit {
+                stdout.write_all(b"\x1b[0m\r\n").unwrap();
+                break;
+            }
+            if state.help {
+                drop(state);
+                show_help(stdout, controls);
+                continue;
+            }
+            if state.paused {
+                drop(cvar.wait(state).unwrap());
+                continue;
+            }
+            drop(state);
+
+            match events.recv_timeout(LIVE_POLL_INTERVAL) {
+                Ok(mut frame) if codes.contains(&frame.code.as_str()) => {
+                    sanitize_frame(&mut filter, &mut frame);
+                    render_frame(
+                        stdout,
+                        &mut status,
+                        &mut fit,
+                        &frame,
+                        self.resize,
+                        pause_on_markers,
+                        controls,
+                    )
+                }
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
     }
 
-    #[test]
-    fn test_play_v1_format_with_speed() {
-        let play = Play::new(test_data_v1_path(), None, 2.0);
-        play.execute();
-    }
+    /// Plays a recording once, following key commands, starting `start_at`
+    /// seconds in.
+    fn play_recording(
+        &self,
+        recording: &Recording,
+        stdout: &mut impl Write,
+        controls: &Controls,
+        pause_on_markers: bool,
+        start_at: Option<f64>,
+        host_size: Option<(u16, u16)>,
+    ) -> Outcome {
+        let header_size = recording.size;
+        let frames = &recording.frames;
+        let duration = recording.duration;
+        let theme = &recording.theme;
+        let mut status = StatusLine::new(host_size.map(|(_, rows)| rows));
+        if self.status {
+            status.progress = Some(Progress {
+                duration,
+                ..Progress::default()
+            });
+        }
+        let mut fit = self.fit_screen(
+            header_size,
+            largest_size(header_size, frames),
+            frames.iter().any(|frame| frame.code == "r"),
+            host_size,
+        );
 
-    #[test]
-    fn test_play_v1_format_with_idle_time_limit() {
-        let play = Play::new(test_data_v1_path(), Some(0.5), 1.0);
-        play.execute();
-    }
+        reset_size(stdout, &mut status, &mut fit, header_size, self.resize);
+        if let Some(theme) = theme {
+            stdout.write_all(theme.as_bytes()).unwrap();
+        }
+        update_progress(stdout, &mut status, controls, 0.0);
+        let mut index = 0;
+        // Position in the recording, after the idle time limit is applied.
+        let mut position = 0.0;
+        let speed = controls.0.lock().unwrap().speed;
+        let mut timeline = Timeline {
+            instant: self.instant,
+            ..Timeline::new(speed)
+        };
 
-    /// Playback of a cast that contains "i" (stdin) events alongside "o" (stdout)
-    /// events should silently skip the input events and only render output events.
-    #[test]
-    fn test_play_skips_stdin_events() {
-        let play = Play::new(test_data_with_stdin_path(), None, 1.0);
-        play.execute();
-    }
+        // Starting later is a seek from the beginning.
+        if let Some(start_at) = start_at {
+            controls.0.lock().unwrap().seek = start_at;
+        }
 
-    /// Timing deltas must be computed only between consecutive "o" events, not
-    /// relative to interleaved "i" events.
-    #[test]
-    fn test_play_stdin_events_do_not_affect_timing() {
-        let play = Play::new(test_data_with_stdin_path(), Some(0.5), 2.0);
-        play.execute();
-    }
+        while index < frames.len() {
+            let (step, seek, marker_jump, skip, changed, quit, help) = {
+                let mut state = controls.0.lock().unwrap();
+                (
+                    std::mem::take(&mut state.step),
+                    std::mem::take(&mut state.seek),
+                    std::mem::take(&mut state.marker_jump),
+                    std::mem::take(&mut state.skip),
+                    std::mem::take(&mut state.changed),
+                    state.quit,
+                    state.help,
+                )
+            };
 
-    /// Verify that wait_interruptible completes without pause after the
-    /// requested delay.
-    #[test]
-    fn test_wait_interruptible_no_pause() {
-        let pair = Arc::new((Mutex::new(false), Condvar::new()));
-        let start = Instant::now();
-        wait_interruptible(&pair, 0.05);
-        assert!(start.elapsed() >= Duration::from_millis(30));
-    }
+            if quit {
+                stdout.write_all(b"\x1b[0m\r\n").unwrap();
+                return Outcome::Quit;
+            }
 
-    /// Verify that wait_interruptible blocks while paused and resumes correctly
-    /// once the pause is lifted, accounting for the time spent paused.
-    #[test]
-    fn test_wait_interruptible_pause_and_resume() {
-        let pair = Arc::new((Mutex::new(false), Condvar::new()));
-        let pair_clone = Arc::clone(&pair);
+            if skip != 0 {
+                stdout.write_all(b"\x1b[0m").unwrap();
+                return Outcome::Skip(skip);
+            }
 
-        // Start paused immediately.
-        {
-            let (lock, cvar) = &*pair;
-            let mut paused = lock.lock().unwrap();
-            *paused = true;
-            cvar.notify_all();
+            if help {
+                show_help(stdout, controls);
+                continue;
+            }
+
+            if changed {
+                update_progress(stdout, &mut status, controls, position);
+            }
+
+            let target = match marker_jump {
+                0 if seek != 0.0 => Some((position + seek).clamp(0.0, duration)),
+                0 => None,
+                direction => marker_target(frames, position, direction),
+            };
+            if let Some(target) = target {
+                if target < position {
+                    // Replay from a reset screen up to the target.
+                    stdout.write_all(b"\x1bc").unwrap();
+                    status.clear();
+                    reset_size(stdout, &mut status, &mut fit, header_size, self.resize);
+                    if let Some(theme) = theme {
+                        stdout.write_all(theme.as_bytes()).unwrap();
+                    }
+                    index = 0;
+                }
+                // Output up to the target is written right away so the screen
+                // is in the right state once timed playback resumes.
+                while index < frames.len() && frames[index].timestamp < target {
+                    let frame = &frames[index];
+                    match (frame.code.as_str(), &mut fit) {
+                        ("o", Some(fit)) => fit.process(&frame.content),
+                        ("o", None) => stdout
+                            .write_all(status.confine(&frame.content).as_bytes())
+                            .unwrap(),
+                        ("m", _) => status.set_marker(&frame.content),
+                        ("r", _) => {
+                            resize(stdout, &mut status, &mut fit, &frame.content, self.resize)
+                        }
+                        _ => {}
+                    }
+                    index += 1;
+                }
+                if let Some(fit) = &mut fit {
+                    fit.draw(stdout);
+                }
+                status.keys.clear();
+                position = target;
+                update_progress(stdout, &mut status, controls, position);
+                timeline.seek(position);
+                stdout.flush().unwrap();
+                continue;
+            }
+
+            let frame = &frames[index];
+            if step {
+                timeline.pause();
+                timeline.seek(frame.timestamp);
+            } else {
+                // With a status line, wake up every second to update the time.
+                let until = match status.progress {
+                    Some(_) => (timeline.now().floor() + 1.0).min(frame.timestamp),
+                    None => frame.timestamp,
+                };
+                if !wait_until(controls, &mut timeline, until) {
+                    // Interrupted by a key; handle it before going on.
+                    position = timeline.now().max(position).min(frame.timestamp);
+                    continue;
+                }
+                if until < frame.timestamp {
+                    position = position.max(until);
+                    update_progress(stdout, &mut status, controls, position);
+                    stdout.flush().unwrap();
+                    continue;
+                }
+            }
+
+            render_frame(
+                stdout,
+                &mut status,
+                &mut fit,
+                frame,
+                self.resize,
+                pause_on_markers,
+                controls,
+            );
+            position = position.max(frame.timestamp);
+            index += 1;
         }
+        update_progress(stdout, &mut status, controls, duration);
+        stdout.flush().unwrap();
 
-        // Lift the pause after 50 ms.
-        thread::spawn(move || {
-            thread::sleep(Duration::from_millis(50));
-            let (lock, cvar) = &*pair_clone;
-            let mut paused = lock.lock().unwrap();
-            *paused = false;
-            cvar.notify_all();
+        Outcome::Finished
+    }
+}
+
+/// Reads a playlist: one recording per line, as accepted by `play`. Blank
+/// lines and lines starting with `#` are skipped, and relative paths are
+/// taken relative to the playlist.
+pub fn read_playlist(path: &str) -> io::Result<Vec<String>> {
+    let dir = std::path::Path::new(path)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            if line == "-" || is_url(line) || line.starts_with("a:") {
+                line.to_string()
+            } else {
+                dir.join(line).to_string_lossy().into_owned()
+            }
+        })
+        .collect())
+}
+
+/// Runs a frame's output through `filter`, if there is one.
+fn sanitize_frame(filter: &mut Option<EscapeFilter>, frame: &mut SessionLine) {
+    if let Some(filter) = filter
+        && frame.code == "o"
+    {
+        frame.content = filter.filter(&frame.content);
+    }
+}
+
+/// Writes a frame that is due: output goes to the terminal, while markers,
+/// keys and resizes update the status line. Pauses at markers if
+/// `pause_on_markers` is set.
+fn render_frame(
+    stdout: &mut impl Write,
+    status: &mut StatusLine,
+    fit: &mut Option<FitScreen>,
+    frame: &SessionLine,
+    resize_terminal: bool,
+    pause_on_markers: bool,
+    controls: &Controls,
+) {
+    match frame.code.as_str() {
+        "m" => {
+            status.set_marker(&frame.content);
+            if pause_on_markers {
+                status.note = Some("(paused, press space to continue)");
+                controls.0.lock().unwrap().paused = true;
+            }
+            status.draw(stdout);
+        }
+        "i" => {
+            status.push_keys(&frame.content, frame.timestamp);
+            status.draw(stdout);
+        }
+        "r" => resize(stdout, status, fit, &frame.content, resize_terminal),
+        _ => match fit {
+            Some(fit) => fit.write(stdout, &frame.content),
+            None => stdout
+                .write_all(status.confine(&frame.content).as_bytes())
+                .unwrap(),
+        },
+    }
+    stdout.flush().unwrap();
+}
+
+/// Returns the OSC sequences setting the terminal colors to `theme`, or
+/// `None` if any color isn't of the form `#rrggbb`.
+fn theme_sequence(theme: &Theme) -> Option<String> {
+    let is_color = |color: &str| {
+        color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+    };
+
+    let palette: Vec<&str> = theme.palette.split(':').collect();
+    if !is_color(&theme.fg)
+        || !is_color(&theme.bg)
+        || !matches!(palette.len(), 8 | 16)
+        || !palette.iter().all(|color| is_color(color))
+    {
+        return None;
+    }
+
+    let mut sequence = format!("\x1b]10;{}\x1b\\\x1b]11;{}\x1b\\", theme.fg, theme.bg);
+    for (index, color) in palette.iter().enumerate() {
+        sequence.push_str(&format!("\x1b]4;{};{}\x1b\\", index, color));
+    }
+    Some(sequence)
+}
+
+/// Parses the `COLSxROWS` data of a resize ("r") event.
+fn parse_resize(data: &str) -> Option<(u16, u16)> {
+    let (cols, rows) = data.split_once('x')?;
+    Some((cols.trim().parse().ok()?, rows.trim().parse().ok()?))
+}
+
+/// Asks the host terminal to resize to `cols`x`rows` (XTWINOPS).
+fn request_size(stdout: &mut impl Write, (cols, rows): (u16, u16)) {
+    write!(stdout, "\x1b[8;{};{}t", rows, cols).unwrap();
+}
+
+/// Returns to the recording's initial size at the start of a pass.
+fn reset_size(
+    stdout: &mut impl Write,
+    status: &mut StatusLine,
+    fit: &mut Option<FitScreen>,
+    size: (u16, u16),
+    terminal: bool,
+) {
+    if let Some(fit) = fit {
+        fit.reset(size);
+        stdout.write_all(b"\x1b[H\x1b[2J").unwrap();
+    }
+    if terminal {
+        request_size(stdout, status.host_size(size));
+    }
+    status.place(screen_rows(size, fit));
+    status.set_scroll_region(stdout);
+}
+
+/// Handles a resize event: asks the host terminal to follow if `terminal` is
+/// set, otherwise clears the screen so the recording's redraw at the new size
+/// doesn't wrap over stale output, and draws the virtual screen, if any,
+/// centered again. The status line moves to the host
+/// terminal's new last row, or below the new screen if its size is unknown.
+fn resize(
+    stdout: &mut impl Write,
+    status: &mut StatusLine,
+    fit: &mut Option<FitScreen>,
+    data: &str,
+    terminal: bool,
+) {
+    let Some(size) = parse_resize(data) else {
+        return;
+    };
+    if let Some(fit) = fit {
+        fit.resize(size);
+    }
+    if terminal {
+        request_size(stdout, status.host_size(size));
+    } else {
+        stdout.write_all(b"\x1b[H\x1b[2J").unwrap();
+    }
+    status.place(screen_rows(size, fit));
+    status.set_scroll_region(stdout);
+    if !terminal {
+        if let Some(fit) = fit {
+            fit.draw(stdout);
+        }
+        status.draw(stdout);
+    }
+}
+
+/// Number of rows the recording takes in the host terminal at `size`.
+fn screen_rows(size: (u16, u16), fit: &Option<FitScreen>) -> u16 {
+    fit.as_ref().map_or(size.1, |fit| fit.view_size().1)
+}
+
+/// The largest size a recording takes at any point, from its header and
+/// resize events, as `(cols, rows)`.
+fn largest_size(header_size: (u16, u16), frames: &[SessionLine]) -> (u16, u16) {
+    frames
+        .iter()
+        .filter(|frame| frame.code == "r")
+        .filter_map(|frame| parse_resize(&frame.content))
+        .fold(header_size, |(w, h), (cols, rows)| {
+            (w.max(cols), h.max(rows))
+        })
+}
+
+/// Returns a warning if the host terminal is smaller than the recording,
+/// plus the row taken by the status line if `status` is set, since the
+/// output would wrap.
+fn size_warning(
+    (cols, rows): (u16, u16),
+    (width, height): (u16, u16),
+    status: bool,
+) -> Option<String> {
+    (cols < width || rows < height + status as u16).then(|| {
+        format!(
+            "warning: the terminal is {}x{}, smaller than the recording's {}x{}{}; \
+             output may wrap (use --fit to crop it or --resize to ask the terminal to resize)",
+            cols,
+            rows,
+            width,
+            height,
+            if status { " and the status line" } else { "" }
+        )
+    })
+}
+
+/// Waits for a key press. Returns false if the key quits.
+fn wait_for_key(controls: &Controls) -> bool {
+    let (lock, cvar) = controls;
+    let mut state = lock.lock().unwrap();
+    state.waiting = true;
+    while state.waiting && !state.quit {
+        state = cvar.wait(state).unwrap();
+    }
+    !state.quit
+}
+
+/// Returns the size of the terminal stdout is attached to, as `(cols, rows)`.
+#[cfg(windows)]
+fn host_terminal_size() -> Option<(u16, u16)> {
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE).ok()?;
+        let mut csbi = CONSOLE_SCREEN_BUFFER_INFO::default();
+        GetConsoleScreenBufferInfo(handle, &mut csbi).ok()?;
+        Some((
+            (csbi.srWindow.Right - csbi.srWindow.Left + 1) as u16,
+            (csbi.srWindow.Bottom - csbi.srWindow.Top + 1) as u16,
+        ))
+    }
+}
+
+/// Returns the size of the terminal stdout is attached to, as `(cols, rows)`.
+#[cfg(unix)]
+fn host_terminal_size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => Some((size.ws_col, size.ws_row)),
+        _ => None,
+    }
+}
+
+/// Returns the position of the next (`direction` 1) or previous (-1) marker
+/// relative to `position`.
+fn marker_target(frames: &[SessionLine], position: f64, direction: i8) -> Option<f64> {
+    let mut markers = frames
+        .iter()
+        .filter(|frame| frame.code == "m")
+        .map(|frame| frame.timestamp);
+    if direction > 0 {
+        markers.find(|&at| at > position)
+    } else {
+        markers.rfind(|&at| at < position - MARKER_GRACE)
+    }
+}
+
+/// Where playback is, for the status line.
+#[derive(Default)]
+struct Progress {
+    position: f64,
+    duration: f64,
+    speed: f64,
+    paused: bool,
+}
+
+/// Updates the status line's progress from the player state, if it shows
+/// progress, and redraws it.
+fn update_progress(
+    stdout: &mut impl Write,
+    status: &mut StatusLine,
+    controls: &Controls,
+    position: f64,
+) {
+    let Some(progress) = &mut status.progress else {
+        return;
+    };
+    let state = controls.0.lock().unwrap();
+    progress.position = position;
+    progress.speed = state.speed;
+    progress.paused = state.paused;
+    drop(state);
+    status.draw(stdout);
+}
+
+/// Formats seconds as `m:ss`, or `h:mm:ss` from an hour on.
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// A line on the host terminal's last row showing the last marker passed
+/// and the keys typed recently, and with `--status` where playback is.
+struct StatusLine {
+    row: i32,
+    /// Rows of the host terminal, if known.
+    host_rows: Option<u16>,
+    marker: Option<String>,
+    keys: String,
+    last_key_at: f64,
+    /// Shown after the marker until the line changes.
+    note: Option<&'static str>,
+    /// Shown when set; the line is then kept out of the recording's way with
+    /// a scroll region.
+    progress: Option<Progress>,
+}
+
+impl StatusLine {
+    fn new(host_rows: Option<u16>) -> Self {
+        StatusLine {
+            row: host_rows.map_or(0, i32::from),
+            host_rows,
+            marker: None,
+            keys: String::new(),
+            last_key_at: 0.0,
+            note: None,
+            progress: None,
+        }
+    }
+
+    /// Moves the line to the host terminal's last row, or right below the
+    /// recording's screen of `rows` rows if the host's size is unknown.
+    fn place(&mut self, rows: u16) {
+        self.row = i32::from(self.host_rows.unwrap_or(rows + 1));
+    }
+
+    /// The size to ask the host terminal for to play at `size`: a row more
+    /// when the line shows progress, so it stays out of the recording's way.
+    fn host_size(&mut self, (cols, rows): (u16, u16)) -> (u16, u16) {
+        let rows = rows + self.progress.is_some() as u16;
+        self.host_rows = Some(rows);
+        (cols, rows)
+    }
+
+    /// Confines scrolling to the rows above the line when it shows progress,
+    /// so that output scrolling up doesn't take the line along.
+    fn set_scroll_region(&self, stdout: &mut impl Write) {
+        if self.progress.is_some() {
+            // Setting the region moves the cursor home; keep it in place.
+            write!(stdout, "\x1b7\x1b[1;{}r\x1b8", self.row - 1).unwrap();
+        }
+    }
+
+    /// Keeps a recording's output from taking the line's row into the scroll
+    /// region while the line shows progress: scroll regions end above it at
+    /// the lowest, and the region and line are set again after a reset (RIS)
+    /// or a switch to or from the alternate screen.
+    fn confine<'a>(&self, data: &'a str) -> Cow<'a, str> {
+        if self.progress.is_none() || !data.contains('\x1b') {
+            return Cow::Borrowed(data);
+        }
+        let mut out = String::with_capacity(data.len());
+        let mut rest = data;
+        while let Some(start) = rest.find('\x1b') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let len = if let Some(csi) = rest.strip_prefix("\x1b[") {
+                csi.find(|c| ('\x40'..='\x7e').contains(&c))
+                    .map_or(rest.len(), |end| end + 3)
+            } else if rest.starts_with("\x1bc") {
+                2
+            } else {
+                1
+            };
+            let (sequence, after) = rest.split_at(len);
+            rest = after;
+
+            if let Some(params) = sequence
+                .strip_prefix("\x1b[")
+                .and_then(|sequence| sequence.strip_suffix('r'))
+                .filter(|params| params.chars().all(|c| c.is_ascii_digit() || c == ';'))
+            {
+                let (top, bottom) = params.split_once(';').unwrap_or((params, ""));
+                let bottom = match bottom.parse::<i32>() {
+                    Ok(bottom) if bottom > 0 => bottom.min(self.row - 1),
+                    _ => self.row - 1,
+                };
+                let top = if top.is_empty() { "1" } else { top };
+                out.push_str(&format!("\x1b[{};{}r", top, bottom));
+            } else if sequence == "\x1bc" || switches_screen(sequence) {
+                out.push_str(sequence);
+                let mut line = Vec::new();
+                self.set_scroll_region(&mut line);
+                self.draw(&mut line);
+                out.push_str(&String::from_utf8(line).unwrap());
+            } else {
+                out.push_str(sequence);
+            }
+        }
+        out.push_str(rest);
+        Cow::Owned(out)
+    }
+
+    fn set_marker(&mut self, label: &str) {
+        self.marker = Some(match label {
+            "" => "Marker".to_string(),
+            name => format!("Marker: {}", plain_text(name)),
         });
+        self.note = None;
+    }
+
+    /// Adds the keys in input event `data`, recorded at `at`.
+    fn push_keys(&mut self, data: &str, at: f64) {
+        // Start over after a pause in typing.
+        if at - self.last_key_at > KEYS_TIMEOUT {
+            self.keys.clear();
+        }
+        self.last_key_at = at;
+        describe_input(&mut self.keys, data);
+
+        let len = self.keys.chars().count();
+        if len > MAX_KEYS_WIDTH {
+            self.keys = self.keys.chars().skip(len - MAX_KEYS_WIDTH).collect();
+        }
+        self.note = None;
+    }
+
+    /// Forgets everything, e.g. after the terminal was reset.
+    fn clear(&mut self) {
+        self.marker = None;
+        self.keys.clear();
+        self.note = None;
+    }
+
+    /// Writes the line, leaving the cursor where it was.
+    fn draw(&self, stdout: &mut impl Write) {
+        let mut parts = Vec::new();
+        if let Some(progress) = &self.progress {
+            parts.push(format!(
+                "{} {} / {}",
+                if progress.paused { "❚❚" } else { "▶" },
+                format_time(progress.position),
+                format_time(progress.duration)
+            ));
+            parts.push(format!("{}x", progress.speed));
+        }
+        if let Some(marker) = &self.marker {
+            parts.push(match self.note {
+                Some(note) => format!("{} {}", marker, note),
+                None => marker.clone(),
+            });
+        }
+        if !self.keys.is_empty() {
+            parts.push(format!("Keys: {}", self.keys.trim()));
+        }
+        if parts.is_empty() {
+            return;
+        }
+
+        write!(
+            stdout,
+            "\x1b7\x1b[{};1H\x1b[7m {} \x1b[0m\x1b[K\x1b8",
+            self.row,
+            parts.join("  |  ")
+        )
+        .unwrap();
+    }
+}
+
+/// Whether `sequence` switches to or from the alternate screen, which may
+/// reset the scroll region.
+fn switches_screen(sequence: &str) -> bool {
+    sequence
+        .strip_prefix("\x1b[?")
+        .and_then(|modes| modes.strip_suffix(['h', 'l']))
+        .is_some_and(|modes| {
+            modes
+                .split(';')
+                .any(|mode| matches!(mode, "47" | "1047" | "1049"))
+        })
+}
+
+/// Renders recorded input readably for the keystroke overlay, e.g. `⏎` for
+/// Enter or `Ctrl-C`, appending to `out`. Special keys are set off by spaces.
+fn describe_input(out: &mut String, data: &str) {
+    fn special(out: &mut String, name: &str) {
+        if !out.is_empty() && !out.ends_with(' ') {
+            out.push(' ');
+        }
+        out.push_str(name);
+        out.push(' ');
+    }
+
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' if matches!(chars.peek(), Some('[' | 'O')) => {
+                chars.next();
+                // Parameters, then the final byte naming the key.
+                let mut params = String::new();
+                let name = loop {
+                    match chars.next() {
+                        Some(c @ '\x30'..='\x3f') => params.push(c),
+                        Some('A') => break Some("↑"),
+                        Some('B') => break Some("↓"),
+                        Some('C') => break Some("→"),
+                        Some('D') => break Some("←"),
+                        Some('H') => break Some("Home"),
+                        Some('F') => break Some("End"),
+                        Some('~') => match params.as_str() {
+                            "2" => break Some("Ins"),
+                            "3" => break Some("Del"),
+                            "5" => break Some("PgUp"),
+                            "6" => break Some("PgDn"),
+                            _ => break None,
+                        },
+                        _ => break None,
+                    }
+                };
+                if let Some(name) = name {
+                    special(out, name);
+                }
+            }
+            '\x1b' => special(out, "Esc"),
+            '\r' | '\n' => special(out, "⏎"),
+            '\t' => special(out, "⇥"),
+            '\x7f' | '\x08' => special(out, "⌫"),
+            '\x00'..='\x1f' => special(out, &format!("Ctrl-{}", (c as u8 + 0x40) as char)),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Shows the key help on the alternate screen until a key is pressed.
+fn show_help(stdout: &mut impl Write, controls: &Controls) {
+    stdout.write_all(b"\x1b[?1049h\x1b[H\x1b[2J").unwrap();
+    stdout.write_all(HELP.as_bytes()).unwrap();
+    stdout.flush().unwrap();
+
+    let (lock, cvar) = controls;
+    let mut state = lock.lock().unwrap();
+    while state.help && !state.quit {
+        state = cvar.wait(state).unwrap();
+    }
+    drop(state);
+
+    stdout.write_all(b"\x1b[?1049l").unwrap();
+    stdout.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Controls, Key, KeyParser, MAX_KEYS_WIDTH, MIN_SPEED, Outcome, PlayerState, Progress,
+        SEEK_STEP, StatusLine, Timeline, describe_input, format_time, handle_key, largest_size,
+        marker_target, parse_resize, read_playlist, resize, size_warning, theme_sequence,
+        wait_for_key, wait_until,
+    };
+    use crate::Play;
+    use crate::commands::session::Session;
+    use crate::commands::types::{RecordHeader, SessionLine, Theme};
+    use std::collections::HashMap;
+    use std::io;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn test_data_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play.txt");
+        d.as_path().to_str().unwrap().to_owned()
+    }
 
+    fn test_data_v1_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play_v1.json");
+        d.as_path().to_str().unwrap().to_owned()
+    }
+
+    fn test_data_with_markers_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play_with_markers.txt");
+        d.as_path().to_str().unwrap().to_owned()
+    }
+
+    fn test_data_with_resize_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play_with_resize.txt");
+        d.as_path().to_str().unwrap().to_owned()
+    }
+
+    fn test_data_with_escapes_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play_with_escapes.txt");
+        d.as_path().to_str().unwrap().to_owned()
+    }
+
+    fn test_data_with_metadata_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play_with_metadata.txt");
+        d.as_path().to_str().unwrap().to_owned()
+    }
+
+    fn test_data_with_corrupt_lines_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play_with_corrupt_lines.txt");
+        d.as_path().to_str().unwrap().to_owned()
+    }
+
+    fn test_data_with_stdin_path() -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("testdata/play_with_stdin.txt");
+        d.as_path().to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_play() {
+        let play = Play::new(vec![test_data_path()], None, 1.0);
+        play.execute();
+    }
+
+    #[test]
+    fn test_play_with_speed() {
+        let play = Play::new(vec![test_data_path()], None, 2.0);
+        play.execute();
+    }
+
+    #[test]
+    fn test_play_with_idle_time_limit() {
+        let play = Play::new(vec![test_data_path()], Some(0.5), 1.0);
+        play.execute();
+    }
+
+    #[test]
+    fn test_play_with_speed_and_idle_time_limit() {
+        let play = Play::new(vec![test_data_path()], Some(0.5), 2.0);
+        play.execute();
+    }
+
+    #[test]
+    fn test_play_v1_format() {
+        let play = Play::new(vec![test_data_v1_path()], None, 1.0);
+        play.execute();
+    }
+
+    #[test]
+    fn test_play_v1_format_with_speed() {
+        let play = Play::new(vec![test_data_v1_path()], None, 2.0);
+        play.execute();
+    }
+
+    #[test]
+    fn test_play_v1_format_with_idle_time_limit() {
+        let play = Play::new(vec![test_data_v1_path()], Some(0.5), 1.0);
+        play.execute();
+    }
+
+    /// Playback of a cast that contains "i" (stdin) events alongside "o" (stdout)
+    /// events should silently skip the input events and only render output events.
+    #[test]
+    fn test_play_skips_stdin_events() {
+        let play = Play::new(vec![test_data_with_stdin_path()], None, 1.0);
+        play.execute();
+    }
+
+    /// Timing deltas must be computed only between consecutive "o" events, not
+    /// relative to interleaved "i" events.
+    #[test]
+    fn test_play_stdin_events_do_not_affect_timing() {
+        let play = Play::new(vec![test_data_with_stdin_path()], Some(0.5), 2.0);
+        play.execute();
+    }
+
+    /// Markers are shown but don't stop playback without keyboard controls.
+    #[test]
+    fn test_play_with_markers() {
+        let play =
+            Play::new(vec![test_data_with_markers_path()], None, 1.0).with_pause_on_markers(true);
+        play.execute();
+    }
+
+    type Writes = Vec<(Instant, Vec<u8>)>;
+
+    /// Collects what the player writes, with the time of each write.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Writes>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().push((Instant::now(), buf.to_vec()));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        fn bytes(&self) -> String {
+            let writes = self.0.lock().unwrap();
+            String::from_utf8(writes.iter().flat_map(|(_, data)| data.clone()).collect()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_play_with_output() {
+        let output = Capture::default();
         let start = Instant::now();
-        // Tiny delay — execution is dominated by the 50 ms pause period.
-        wait_interruptible(&pair, 0.001);
-        let elapsed = start.elapsed();
+        Play::new(vec![test_data_with_resize_path()], None, 1.0)
+            .with_output(output.clone())
+            .with_instant(true)
+            .execute();
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(output.bytes(), "a\x1b[H\x1b[2Jb");
+    }
 
-        // Must have waited at least ~50 ms for the resume signal.
-        assert!(elapsed >= Duration::from_millis(30));
+    #[test]
+    fn test_play_with_output_timing() {
+        let output = Capture::default();
+        Play::new(vec![test_data_with_resize_path()], None, 2.0)
+            .with_output(output.clone())
+            .execute();
+
+        // "a" and "b" are 200 ms apart, so 100 ms at double speed, give or
+        // take a little for when "a" was written.
+        let writes = output.0.lock().unwrap();
+        let at = |event: &[u8]| writes.iter().find(|(_, data)| data == event).unwrap().0;
+        let gap = at(b"b").duration_since(at(b"a"));
+        assert!(
+            gap >= Duration::from_millis(90) && gap < Duration::from_millis(150),
+            "{:?} apart",
+            gap
+        );
     }
 
     #[test]
-    fn test_is_url() {
-        assert!(is_url("https://asciinema.org/a/abc123"));
-        assert!(is_url("http://example.com/recording.cast"));
-        assert!(!is_url("my_recording.cast"));
-        assert!(!is_url("/path/to/recording.cast"));
-        assert!(!is_url("C:\\recordings\\session.cast"));
+    fn test_play_playlist() {
+        let output = Capture::default();
+        Play::new(
+            vec![test_data_with_resize_path(), test_data_with_metadata_path()],
+            None,
+            1.0,
+        )
+        .with_output(output.clone())
+        .with_instant(true)
+        .with_title_cards(true)
+        .execute();
+
+        let output = output.bytes();
+        let first = output.find("1/2").unwrap();
+        let second = output.find("2/2\x1b[0m  Demo").unwrap();
+        assert!(first < output.find("a\x1b[H\x1b[2Jb").unwrap());
+        assert!(output.find("a\x1b[H\x1b[2Jb").unwrap() < second);
+        assert!(second < output.find("before the pause").unwrap());
+        assert!(!output.contains("Recorded at") && !output.contains("Demo (100x30)"));
     }
 
+    /// Markers and titles are shown as plain text, whatever the recording
+    /// has in them.
     #[test]
-    fn test_normalize_url_asciinema() {
-        // Basic recording URL
-        assert_eq!(
-            normalize_url("https://asciinema.org/a/abc123"),
-            "https://asciinema.org/a/abc123.cast"
+    fn test_play_strips_labels() {
+        let output = Capture::default();
+        Play::new(
+            vec![test_data_with_escapes_path(), test_data_with_escapes_path()],
+            None,
+            1.0,
+        )
+        .with_output(output.clone())
+        .with_instant(true)
+        .with_title_cards(true)
+        .execute();
+
+        let output = output.bytes();
+        assert!(output.contains("2/2\x1b[0m  Demo6n\r\n"));
+        assert!(output.contains("Marker: Setup "));
+        assert!(!output.contains("\x1b]") && !output.contains("\x1b[6n"));
+        assert!(!output.contains('\u{9b}') && !output.contains('\x07'));
+    }
+
+    #[test]
+    fn test_play_recording_fit() {
+        let play = Play::new(vec![test_data_with_resize_path()], None, 1.0)
+            .with_instant(true)
+            .with_fit(true);
+        let recording = play.load(&test_data_with_resize_path()).unwrap();
+        let controls = controls();
+
+        // The recording is drawn through a virtual screen in 40x10.
+        let mut output = Vec::new();
+        play.play_recording(
+            &recording,
+            &mut output,
+            &controls,
+            false,
+            None,
+            Some((40, 10)),
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("\x1b[1;1H\x1b[0m\x1b[2Kab\x1b[0m\x1b[1;3H\x1b[?25h"));
+
+        // At its largest it's 100x30, which fits, so it's kept centered as it
+        // is resized.
+        let mut output = Vec::new();
+        play.play_recording(
+            &recording,
+            &mut output,
+            &controls,
+            false,
+            None,
+            Some((100, 30)),
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("\x1b[H\x1b[2J\x1b[4;11H\x1b[0m\x1b[2Ka"));
+        assert!(output.contains("\x1b[H\x1b[2J\x1b[1;1H\x1b[0m\x1b[2Ka\x1b[2;1H"));
+        assert!(output.ends_with("\x1b[1;1H\x1b[0m\x1b[2Kab\x1b[0m\x1b[1;3H\x1b[?25h"));
+
+        // With --resize, the terminal is asked to follow instead.
+        let play = Play::new(vec![test_data_with_resize_path()], None, 1.0)
+            .with_instant(true)
+            .with_resize(true);
+        let mut output = Vec::new();
+        play.play_recording(
+            &recording,
+            &mut output,
+            &controls,
+            false,
+            None,
+            Some((100, 30)),
         );
-        // Already has .cast – should not double-append
+        assert_eq!(output, b"\x1b[8;24;80ta\x1b[8;30;100tb");
+    }
+
+    /// A skip key ends the recording being played.
+    #[test]
+    fn test_play_recording_skip() {
+        let play = Play::new(vec![test_data_path()], None, 1.0).with_instant(true);
+        let recording = play.load(&test_data_path()).unwrap();
+        let controls = controls();
+
+        let mut output = Vec::new();
         assert_eq!(
-            normalize_url("https://asciinema.org/a/abc123.cast"),
-            "https://asciinema.org/a/abc123.cast"
+            play.play_recording(&recording, &mut output, &controls, false, None, None),
+            Outcome::Finished
         );
-        // Non-asciinema URL – should be returned unchanged
+        controls.0.lock().unwrap().skip = -1;
         assert_eq!(
-            normalize_url("https://example.com/recording.cast"),
-            "https://example.com/recording.cast"
+            play.play_recording(&recording, &mut output, &controls, false, None, None),
+            Outcome::Skip(-1)
         );
-        // URL with query string – .cast inserted before '?'
+        controls.0.lock().unwrap().quit = true;
         assert_eq!(
-            normalize_url("https://asciinema.org/a/abc123?t=10"),
-            "https://asciinema.org/a/abc123.cast?t=10"
+            play.play_recording(&recording, &mut output, &controls, false, None, None),
+            Outcome::Quit
         );
-        // URL with trailing slash – slash stripped before appending .cast
+    }
+
+    /// Positions are the recorded times, including the first event's, with
+    /// the idle time limit applied to the gaps.
+    #[test]
+    fn test_load_positions() {
+        let play = Play::new(vec![], None, 1.0);
+        let positions = |frames: &[SessionLine]| -> Vec<f64> {
+            frames.iter().map(|frame| frame.timestamp).collect()
+        };
+        let recording = play.load(&test_data_with_resize_path()).unwrap();
+        assert_eq!(positions(&recording.frames), [0.1, 0.2, 0.3]);
+        let recording = play.load(&test_data_with_metadata_path()).unwrap();
+        assert_eq!(positions(&recording.frames), [0.1, 0.6]);
+
+        // An event right at the start doesn't shift the ones after it.
+        let path = std::env::temp_dir().join(format!("powersession-load-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "{\"version\":2,\"width\":80,\"height\":24,\"timestamp\":1,\"env\":{}}\n\
+             [0.0,\"o\",\"a\"]\n[1.5,\"o\",\"b\"]\n[2.0,\"o\",\"c\"]\n",
+        )
+        .unwrap();
+        let recording = play.load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(positions(&recording.frames), [0.0, 1.5, 2.0]);
+        assert_eq!(recording.duration, 2.0);
+    }
+
+    #[test]
+    fn test_read_playlist() {
+        let dir =
+            std::env::temp_dir().join(format!("powersession-playlist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("list.txt");
+        std::fs::write(
+            &path,
+            "# workshop\nintro.cast\n\n  https://asciinema.org/a/abc  \na:xyz\n",
+        )
+        .unwrap();
+
+        let items = read_playlist(path.to_str().unwrap()).unwrap();
         assert_eq!(
-            normalize_url("https://asciinema.org/a/abc123/"),
-            "https://asciinema.org/a/abc123.cast"
+            items,
+            [
+                dir.join("intro.cast").to_str().unwrap(),
+                "https://asciinema.org/a/abc",
+                "a:xyz"
+            ]
         );
-        // URL with fragment – .cast inserted before '#'
+        std::fs::remove_dir_all(dir).unwrap();
+        assert!(read_playlist("does-not-exist.txt").is_err());
+    }
+
+    /// Live streams come from elsewhere, so their output is filtered.
+    #[test]
+    fn test_play_live_sanitized() {
+        let (tx, rx) = channel();
+        for data in ["a\x1b]52;c;ZXZpbA==", "\x07b\x1b[6n", "\x1b[1mc"] {
+            tx.send(SessionLine {
+                timestamp: 0.0,
+                code: "o".to_string(),
+                content: data.to_string(),
+            })
+            .unwrap();
+        }
+        drop(tx);
+
+        let header = RecordHeader {
+            version: 2,
+            width: 80,
+            height: 24,
+            timestamp: 0,
+            environment: HashMap::new(),
+            invalid_utf8: None,
+            idle_time_limit: None,
+            title: None,
+            theme: None,
+        };
+        let output = Capture::default();
+        Play::live(Session::live(header, rx))
+            .with_output(output.clone())
+            .execute();
+        assert_eq!(output.bytes(), "ab\x1b[1mc");
+    }
+
+    #[test]
+    fn test_play_lenient() {
+        let output = Capture::default();
+        Play::new(vec![test_data_with_corrupt_lines_path()], None, 1.0)
+            .with_lenient(true)
+            .with_output(output.clone())
+            .with_instant(true)
+            .execute();
+        // The three corrupt lines are skipped.
+        assert_eq!(output.bytes(), "first\r\nlast\r\n");
+
+        // Without it, the first corrupt line is an error.
+        let play = Play::new(vec![], None, 1.0);
+        let Err(error) = play.load(&test_data_with_corrupt_lines_path()) else {
+            panic!("corrupt lines were accepted");
+        };
+        assert!(error.ends_with("line 3: corrupt record: expected 3 fields, got 2"));
+    }
+
+    #[test]
+    fn test_play_with_loop() {
+        let start = Instant::now();
+        let play =
+            Play::new(vec![test_data_with_markers_path()], None, 1.0).with_loop(Some(3), 0.1);
+        play.execute();
+        // Three passes of 0.7s with two gaps in between.
+        assert!(start.elapsed() >= Duration::from_millis(2200));
+    }
+
+    #[test]
+    fn test_play_with_show_input() {
+        let play = Play::new(vec![test_data_with_stdin_path()], None, 2.0).with_show_input(true);
+        play.execute();
+    }
+
+    #[test]
+    fn test_describe_input() {
+        let describe_input = |data| {
+            let mut out = String::new();
+            describe_input(&mut out, data);
+            out
+        };
+        assert_eq!(describe_input("ls -la"), "ls -la");
+        assert_eq!(describe_input("ls\r"), "ls ⏎ ");
+        assert_eq!(describe_input("\x03"), "Ctrl-C ");
+        assert_eq!(describe_input("\x1b[A\x1b[A"), "↑ ↑ ");
+        assert_eq!(describe_input("\x1bOD\x1b[3~\x7f"), "← Del ⌫ ");
+        assert_eq!(describe_input("\x1b:q\r"), "Esc :q ⏎ ");
+        assert_eq!(describe_input("\x1b[1;5C"), "→ ");
+    }
+
+    #[test]
+    fn test_status_line_keys() {
+        let mut status = StatusLine::new(Some(25));
+        status.push_keys("git status", 1.0);
+        status.push_keys("\r", 1.5);
+        assert_eq!(status.keys, "git status ⏎ ");
+
+        // Typing again after a while starts over.
+        status.push_keys("q", 10.0);
+        assert_eq!(status.keys, "q");
+
+        status.push_keys(&"x".repeat(100), 10.5);
+        assert_eq!(status.keys.len(), MAX_KEYS_WIDTH);
+
+        let mut out = Vec::new();
+        status.set_marker("Setup");
+        status.draw(&mut out);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\x1b7\x1b[25;1H"));
+        assert!(out.contains("Marker: Setup  |  Keys: xxx"));
+    }
+
+    #[test]
+    fn test_status_line_progress() {
+        let mut status = StatusLine::new(Some(25));
+        status.progress = Some(Progress {
+            position: 75.0,
+            duration: 4000.0,
+            speed: 0.5,
+            paused: true,
+        });
+        status.set_marker("Setup");
+
+        let mut out = Vec::new();
+        status.set_scroll_region(&mut out);
+        status.draw(&mut out);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\x1b7\x1b[1;24r\x1b8"));
+        assert!(out.contains(" ❚❚ 1:15 / 1:06:40  |  0.5x  |  Marker: Setup "));
+
+        assert_eq!(status.confine("a\x1b[rb"), "a\x1b[1;24rb");
+        assert_eq!(status.confine("\x1b[2;10r"), "\x1b[2;10r");
+        // Full-height regions end above the line.
         assert_eq!(
-            normalize_url("https://asciinema.org/a/abc123#intro"),
-            "https://asciinema.org/a/abc123.cast#intro"
+            status.confine("\x1b[1;25r\x1b[;40r"),
+            "\x1b[1;24r\x1b[1;24r"
+        );
+        assert_eq!(status.confine("\x1b[2;0r\x1b[1m"), "\x1b[2;24r\x1b[1m");
+        // The region and line are set again after a reset or screen switch.
+        let out = status.confine("a\x1bcb");
+        assert!(out.starts_with("a\x1bc\x1b7\x1b[1;24r\x1b8\x1b7\x1b[25;1H"));
+        assert!(out.ends_with("\x1b8b"));
+        let out = status.confine("\x1b[?1049h\x1b[?25;47l");
+        assert_eq!(out.matches("\x1b[1;24r").count(), 2);
+        assert_eq!(status.confine("\x1b[?25l\x1b7"), "\x1b[?25l\x1b7");
+        status.progress = None;
+        assert_eq!(status.confine("\x1b[r"), "\x1b[r");
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0.0), "0:00");
+        assert_eq!(format_time(59.9), "0:59");
+        assert_eq!(format_time(754.0), "12:34");
+        assert_eq!(format_time(3661.0), "1:01:01");
+    }
+
+    #[test]
+    fn test_play_with_status() {
+        let output = Capture::default();
+        Play::new(vec![test_data_with_resize_path()], None, 1.0)
+            .with_output(output.clone())
+            .with_instant(true)
+            .with_status(true)
+            .execute();
+        let out = output.bytes();
+        assert!(out.contains("\x1b7\x1b[1;24r\x1b8"));
+        assert!(out.contains("▶ 0:00 / 0:00  |  1x"));
+        assert!(out.ends_with("\x1b7\x1b[r\x1b8"));
+    }
+
+    #[test]
+    fn test_play_with_resize() {
+        let output = Capture::default();
+        Play::new(vec![test_data_with_resize_path()], None, 1.0)
+            .with_resize(true)
+            .with_output(output.clone())
+            .with_instant(true)
+            .execute();
+        // The terminal is asked for the recording's size, then follows it.
+        assert_eq!(output.bytes(), "\x1b[8;24;80ta\x1b[8;30;100tb");
+    }
+
+    #[test]
+    fn test_parse_resize() {
+        assert_eq!(parse_resize("100x30"), Some((100, 30)));
+        assert_eq!(parse_resize(" 80 x 24 "), Some((80, 24)));
+        assert_eq!(parse_resize("100"), None);
+        assert_eq!(parse_resize("ax30"), None);
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut status = StatusLine::new(None);
+        let mut out = Vec::new();
+        resize(&mut out, &mut status, &mut None, "80x24", false);
+        assert_eq!(out, b"\x1b[H\x1b[2J");
+        assert_eq!(status.row, 25);
+
+        // The terminal is asked to resize, and the line is on its last row.
+        let mut out = Vec::new();
+        resize(&mut out, &mut status, &mut None, "100x30", true);
+        assert_eq!(out, b"\x1b[8;30;100t");
+        assert_eq!(status.row, 30);
+
+        // With progress shown, a row more is asked for and kept for the line.
+        status.progress = Some(Progress::default());
+        let mut out = Vec::new();
+        resize(&mut out, &mut status, &mut None, "100x30", true);
+        assert_eq!(out, b"\x1b[8;31;100t\x1b7\x1b[1;30r\x1b8");
+        assert_eq!(status.row, 31);
+
+        // Without resizing the terminal, the line stays on its last row.
+        let mut status = StatusLine::new(Some(40));
+        status.progress = Some(Progress::default());
+        let mut out = Vec::new();
+        resize(&mut out, &mut status, &mut None, "80x24", false);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\x1b[H\x1b[2J\x1b7\x1b[1;39r\x1b8"));
+        assert_eq!(status.row, 40);
+    }
+
+    #[test]
+    fn test_restore_size() {
+        let play = Play::new(vec![test_data_with_resize_path()], None, 1.0);
+        let mut out = Vec::new();
+        play.restore_size(&mut out, Some((120, 40)));
+        assert!(out.is_empty());
+
+        let play = play.with_resize(true);
+        play.restore_size(&mut out, None);
+        assert!(out.is_empty());
+        play.restore_size(&mut out, Some((120, 40)));
+        assert_eq!(out, b"\x1b[8;40;120t");
+    }
+
+    #[test]
+    fn test_size_warning() {
+        let frames = vec![SessionLine {
+            timestamp: 1.0,
+            code: "r".to_string(),
+            content: "120x20".to_string(),
+        }];
+        assert_eq!(largest_size((80, 24), &frames), (120, 24));
+
+        assert_eq!(size_warning((120, 24), (120, 24), false), None);
+        let warning = size_warning((100, 40), (120, 24), false).unwrap();
+        assert!(warning.contains("100x40, smaller than the recording's 120x24;"));
+        // The status line takes a row.
+        let warning = size_warning((120, 24), (120, 24), true).unwrap();
+        assert!(warning.contains("recording's 120x24 and the status line;"));
+    }
+
+    #[test]
+    fn test_wait_for_key() {
+        let controls: Arc<Controls> = Arc::new((Mutex::new(PlayerState::new(1.0)), Condvar::new()));
+        let waiter = {
+            let controls = Arc::clone(&controls);
+            thread::spawn(move || wait_for_key(&controls))
+        };
+        while !controls.0.lock().unwrap().waiting {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // The key only ends the wait.
+        handle_key(&mut controls.0.lock().unwrap(), Key::Char(b' '));
+        controls.1.notify_all();
+        assert!(waiter.join().unwrap());
+        assert!(!controls.0.lock().unwrap().paused);
+
+        let mut state = PlayerState::new(1.0);
+        state.waiting = true;
+        handle_key(&mut state, Key::Char(b'q'));
+        assert!(!state.waiting && state.quit);
+    }
+
+    #[test]
+    fn test_play_with_header_metadata() {
+        let start = Instant::now();
+        let play = Play::new(vec![test_data_with_metadata_path()], None, 1.0);
+        play.execute();
+        // The header's idle time limit cuts the 5s pause to 0.5s.
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_theme_sequence() {
+        let mut theme = Theme {
+            fg: "#d0d0d0".to_string(),
+            bg: "#212121".to_string(),
+            palette: ["#000000", "#ff0000", "#00ff00", "#ffff00"]
+                .repeat(2)
+                .join(":"),
+        };
+        let sequence = theme_sequence(&theme).unwrap();
+        assert!(sequence.starts_with("\x1b]10;#d0d0d0\x1b\\\x1b]11;#212121\x1b\\"));
+        assert!(sequence.ends_with("\x1b]4;7;#ffff00\x1b\\"));
+
+        // Anything that could smuggle in other escape sequences is refused.
+        theme.fg = "#d0d0d0\x07\x1b]52;c;aGk=".to_string();
+        assert!(theme_sequence(&theme).is_none());
+        theme.fg = "#d0d0d0".to_string();
+        theme.palette = "#000000:#ffffff".to_string();
+        assert!(theme_sequence(&theme).is_none());
+    }
+
+    #[test]
+    fn test_marker_target() {
+        let frames: Vec<SessionLine> = [(1.0, "m"), (2.0, "o"), (5.0, "m"), (9.0, "m")]
+            .into_iter()
+            .map(|(timestamp, code)| SessionLine {
+                timestamp,
+                code: code.to_string(),
+                content: String::new(),
+            })
+            .collect();
+
+        assert_eq!(marker_target(&frames, 0.0, 1), Some(1.0));
+        assert_eq!(marker_target(&frames, 1.0, 1), Some(5.0));
+        assert_eq!(marker_target(&frames, 9.0, 1), None);
+        assert_eq!(marker_target(&frames, 7.0, -1), Some(5.0));
+        // Right after a marker, go to the one before it.
+        assert_eq!(marker_target(&frames, 5.5, -1), Some(1.0));
+        assert_eq!(marker_target(&frames, 1.5, -1), None);
+    }
+
+    #[test]
+    fn test_play_with_start_at() {
+        let start = Instant::now();
+        let play = Play::new(vec![test_data_path()], None, 1.0).with_start_at(Some(3.0));
+        play.execute();
+        // The recording is ~3.1s long; only the tail is played with delays.
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_play_v1_format_with_start_at() {
+        let play = Play::new(vec![test_data_v1_path()], Some(0.5), 1.0).with_start_at(Some(1.0));
+        play.execute();
+    }
+
+    fn controls() -> Arc<Controls> {
+        Arc::new((Mutex::new(PlayerState::new(1.0)), Condvar::new()))
+    }
+
+    /// Verify that wait_until completes without pause after the delay.
+    #[test]
+    fn test_wait_until_no_pause() {
+        let controls = controls();
+        let start = Instant::now();
+        assert!(wait_until(&controls, &mut Timeline::new(1.0), 0.05));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    /// Verify that wait_until blocks while paused and resumes correctly
+    /// once the pause is lifted, accounting for the time spent paused.
+    #[test]
+    fn test_wait_until_pause_and_resume() {
+        let controls = controls();
+        let controls_clone = Arc::clone(&controls);
+
+        // Start paused immediately.
+        {
+            let (lock, cvar) = &*controls;
+            lock.lock().unwrap().paused = true;
+            cvar.notify_all();
+        }
+
+        // Lift the pause after 50 ms.
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let (lock, cvar) = &*controls_clone;
+            lock.lock().unwrap().paused = false;
+            cvar.notify_all();
+        });
+
+        let start = Instant::now();
+        let mut timeline = Timeline::new(1.0);
+        assert!(wait_until(&controls, &mut timeline, 0.02));
+        let elapsed = start.elapsed();
+
+        // The pause doesn't count towards the 20 ms of recording time.
+        assert!(elapsed >= Duration::from_millis(70));
+        assert!(timeline.now() >= 0.02);
+    }
+
+    /// A key command cuts the wait short; the timeline tells how far it got.
+    #[test]
+    fn test_wait_until_seek() {
+        let controls = controls();
+        let controls_clone = Arc::clone(&controls);
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let (lock, cvar) = &*controls_clone;
+            handle_key(&mut lock.lock().unwrap(), Key::Right);
+            cvar.notify_all();
+        });
+
+        let start = Instant::now();
+        let mut timeline = Timeline::new(1.0);
+        assert!(!wait_until(&controls, &mut timeline, 10.0));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let position = timeline.now();
+        assert!(position > 0.0 && position < 1.0);
+    }
+
+    /// Time spent writing each event doesn't accumulate: thousands of events
+    /// still end on time.
+    #[test]
+    fn test_wait_until_no_drift() {
+        let controls = controls();
+        let mut timeline = Timeline::new(1.0);
+        let start = Instant::now();
+        let mut writing = Duration::ZERO;
+        for i in 1..=2000 {
+            assert!(wait_until(&controls, &mut timeline, i as f64 * 0.001));
+            // Stand-in for writing the event.
+            let write_start = Instant::now();
+            thread::sleep(Duration::from_micros(100));
+            writing += write_start.elapsed();
+        }
+        // Drifting would add all the time spent writing to the end.
+        let drift = start.elapsed().as_secs_f64() - 2.0;
+        let writing = writing.as_secs_f64();
+        assert!(
+            (0.0..writing / 10.0).contains(&drift),
+            "drifted {}s, with {}s spent writing",
+            drift,
+            writing
         );
-        // URL with both query string and fragment
+    }
+
+    #[test]
+    fn test_key_parser() {
+        let mut parser = KeyParser::default();
+        let keys: Vec<Key> = b" q\x1b[C\x1b[D\x1bOA\x1b[1;5B\x1b[3~."
+            .iter()
+            .filter_map(|&b| parser.feed(b))
+            .collect();
         assert_eq!(
-            normalize_url("https://asciinema.org/a/abc123?t=10#intro"),
-            "https://asciinema.org/a/abc123.cast?t=10#intro"
+            keys,
+            vec![
+                Key::Char(b' '),
+                Key::Char(b'q'),
+                Key::Right,
+                Key::Left,
+                Key::Up,
+                Key::Down,
+                Key::Char(b'.'),
+            ]
         );
     }
+
+    #[test]
+    fn test_handle_key() {
+        let mut state = PlayerState::new(1.0);
+
+        // Stepping only works while paused.
+        handle_key(&mut state, Key::Char(b'.'));
+        assert!(!state.step);
+        handle_key(&mut state, Key::Char(b' '));
+        handle_key(&mut state, Key::Char(b'.'));
+        assert!(state.paused && state.step && state.changed);
+
+        handle_key(&mut state, Key::Char(b'+'));
+        assert_eq!(state.speed, 2.0);
+        for _ in 0..10 {
+            handle_key(&mut state, Key::Char(b'-'));
+        }
+        assert_eq!(state.speed, MIN_SPEED);
+
+        handle_key(&mut state, Key::Right);
+        handle_key(&mut state, Key::Right);
+        handle_key(&mut state, Key::Left);
+        assert_eq!(state.seek, SEEK_STEP);
+
+        handle_key(&mut state, Key::Char(b'p'));
+        assert_eq!(state.marker_jump, -1);
+
+        handle_key(&mut state, Key::Char(b']'));
+        assert_eq!(state.skip, 1);
+        handle_key(&mut state, Key::Char(b'['));
+        assert_eq!(state.skip, -1);
+
+        // While help is shown any key only dismisses it.
+        handle_key(&mut state, Key::Char(b'?'));
+        assert!(state.help);
+        handle_key(&mut state, Key::Char(b'q'));
+        assert!(!state.help && !state.quit);
+        handle_key(&mut state, Key::Char(0x03));
+        assert!(state.quit);
+    }
 }
@@ -2,14 +2,16 @@ use std::path::Path;
 use std::process::exit;
 
 use log::{error, trace};
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use std::sync::Mutex;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 use std::{
     collections::HashMap,
     env, fs,
     fs::File,
-    io::Write,
+    io,
+    io::{IsTerminal, Write},
     sync::Arc,
     thread,
 };
@@ -21,23 +23,66 @@ use windows::Win32::{
     System::Console::{GetStdHandle, WriteConsoleW, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE},
 };
 
+use crate::commands::api::ApiService;
+use crate::commands::duration::format_duration;
+use crate::commands::encoding::{
+    InputEncoding, InvalidUtf8Policy, OutputDecoder, Utf8Decoder, PENDING_FLUSH_TIMEOUT,
+};
+use crate::commands::limit::OutputLimiter;
 use crate::commands::types::LineItem;
 use crate::commands::types::RecordHeader;
+use crate::commands::upload::Upload;
 #[cfg(windows)]
 use crate::terminal::Terminal;
 #[cfg(windows)]
 use crate::terminal::WindowsTerminal;
 
+/// Environment variable exported to recorded programs, holding where the
+/// session is being recorded to.
+pub const RECORDING_ENV: &str = "POWERSESSION_RECORDING";
+
+/// Refuse to run inside a session that is already being recorded, as the outer
+/// recorder would capture all output twice. With `nested` set, only warn.
+pub fn check_nested(nested: bool) {
+    if let Ok(outer) = env::var(RECORDING_ENV) {
+        if nested {
+            eprintln!("warning: this session is already being recorded to {}", outer);
+        } else {
+            eprintln!("this session is already being recorded to {}", outer);
+            eprintln!("use --nested to record anyway");
+            exit(1);
+        }
+    }
+}
+
 pub struct Record {
     output_writer: Arc<Mutex<Box<dyn Write + Send + Sync>>>,
     filename: String,
     env: HashMap<String, String>,
     command: String,
     stdin: bool,
+    invalid_utf8: InvalidUtf8Policy,
+    input_encoding: Option<InputEncoding>,
+    /// Builds the api service once an upload is chosen; building it sets up
+    /// the config file.
+    api_service: Option<fn() -> Box<dyn ApiService>>,
+    upload: bool,
+    upload_delete: bool,
+    max_duration: Option<Duration>,
+    stop_after_idle: Option<Duration>,
+    max_output_bytes: Option<u64>,
+    max_output_rate: Option<u64>,
     #[cfg(windows)]
     terminal: WindowsTerminal,
 }
 
+/// What to do with the cast once the session has ended.
+enum SaveAction {
+    Save,
+    Upload,
+    Discard,
+}
+
 impl Record {
     pub fn new(
         filename: String,
@@ -64,10 +109,69 @@ impl Record {
             command: command
                 .unwrap_or_else(|| env::var("SHELL").unwrap_or("powershell.exe".to_owned())),
             stdin,
+            invalid_utf8: InvalidUtf8Policy::Replace,
+            input_encoding: None,
+            api_service: None,
+            upload: false,
+            upload_delete: false,
+            max_duration: None,
+            stop_after_idle: None,
+            max_output_bytes: None,
+            max_output_rate: None,
             #[cfg(windows)]
             terminal: WindowsTerminal::new(None),
         }
     }
+
+    /// Set how output that is not valid UTF-8 is written to the cast.
+    pub fn with_invalid_utf8(mut self, policy: InvalidUtf8Policy) -> Self {
+        self.invalid_utf8 = policy;
+        self
+    }
+
+    /// Set the legacy encoding the recorded program reads and writes. Its
+    /// output is transcoded to UTF-8 and keyboard input is transcoded back.
+    pub fn with_input_encoding(mut self, encoding: Option<InputEncoding>) -> Self {
+        self.input_encoding = encoding;
+        self
+    }
+
+    /// Set how to build the api service used to upload the cast when the
+    /// session ends; it is only built if the cast is uploaded. With `upload`
+    /// set the cast is uploaded without asking; otherwise an interactive
+    /// session is asked whether to save, upload or discard it. With `delete`
+    /// set the local file is removed after a successful upload.
+    pub fn with_upload(
+        mut self,
+        api_service: fn() -> Box<dyn ApiService>,
+        upload: bool,
+        delete: bool,
+    ) -> Self {
+        self.api_service = Some(api_service);
+        self.upload = upload;
+        self.upload_delete = delete;
+        self
+    }
+
+    /// Stop the recording once it has run for `max_duration`, or once neither
+    /// output nor input has happened for `stop_after_idle`.
+    pub fn with_limits(
+        mut self,
+        max_duration: Option<Duration>,
+        stop_after_idle: Option<Duration>,
+    ) -> Self {
+        self.max_duration = max_duration;
+        self.stop_after_idle = stop_after_idle;
+        self
+    }
+
+    /// Cap the output written to the cast at `max_bytes` in total and at
+    /// `max_rate` bytes per second. The console still shows all output.
+    pub fn with_output_caps(mut self, max_bytes: Option<u64>, max_rate: Option<u64>) -> Self {
+        self.max_output_bytes = max_bytes;
+        self.max_output_rate = max_rate;
+        self
+    }
     pub fn execute(&mut self) {
         self.env.insert(
             "SHELL".to_string(),
@@ -82,15 +186,75 @@ impl Record {
             self.env.insert("TERM".to_string(), term);
         }
 
-        self.record();
+        let keys = self.record();
+        self.finish(keys);
     }
 
-    fn record(&mut self) {
+    /// Save, upload or discard the finished cast. `keys` receives the
+    /// keystrokes typed after the recorded command exited.
+    fn finish(&mut self, keys: Receiver<u8>) {
+        // Close the cast file so it can be uploaded or removed.
+        *self.output_writer.lock().unwrap() = Box::new(io::sink());
+
+        let action = match self.api_service {
+            None => SaveAction::Save,
+            Some(_) if self.upload => SaveAction::Upload,
+            Some(_) if io::stdin().is_terminal() => Self::prompt(keys),
+            Some(_) => SaveAction::Save,
+        };
+
+        match action {
+            SaveAction::Save => {
+                println!("Record finished. Result saved to file {}", self.filename);
+            }
+            SaveAction::Upload => {
+                let api_service = self.api_service.unwrap()();
+                let uploaded = Upload::new(api_service, self.filename.clone()).execute();
+                if uploaded.is_none() {
+                    println!("Result saved to file {}", self.filename);
+                } else if self.upload_delete {
+                    match fs::remove_file(&self.filename) {
+                        Ok(()) => println!("Local file {} deleted", self.filename),
+                        Err(e) => eprintln!("failed to delete {}: {}", self.filename, e),
+                    }
+                }
+            }
+            SaveAction::Discard => match fs::remove_file(&self.filename) {
+                Ok(()) => println!("Record discarded"),
+                Err(e) => eprintln!(
+                    "failed to discard the record, it is kept in {}: {}",
+                    self.filename, e
+                ),
+            },
+        }
+    }
+
+    fn prompt(keys: Receiver<u8>) -> SaveAction {
+        print!("Record finished. [s]ave locally, [u]pload or [d]iscard? (default: save) ");
+        io::stdout().flush().unwrap();
+
+        let action = loop {
+            match keys.recv() {
+                Ok(b'u' | b'U') => break SaveAction::Upload,
+                Ok(b'd' | b'D') => break SaveAction::Discard,
+                // Enter and Ctrl-C keep the default, as does a closed stdin.
+                Ok(b's' | b'S' | b'\r' | b'\n' | 0x03) | Err(_) => break SaveAction::Save,
+                Ok(_) => continue,
+            }
+        };
+        println!();
+        action
+    }
+
+    /// Record the session. Returns the keystrokes typed once the recorded
+    /// command has exited.
+    fn record(&mut self) -> Receiver<u8> {
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("check your machine time");
 
         let record_start_time = now.as_secs() as f64 + now.subsec_nanos() as f64 * 1e-9;
+        let started = Instant::now();
 
         let header = RecordHeader {
             version: 2,
@@ -104,6 +268,10 @@ impl Record {
             height: 24,
             timestamp: record_start_time as u64,
             environment: self.env.clone(),
+            invalid_utf8: Some(self.invalid_utf8),
+            idle_time_limit: None,
+            title: None,
+            theme: None,
         };
 
         self.output_writer
@@ -123,6 +291,12 @@ impl Record {
         //   None       – stdout closed; recording is done
         let (event_tx, event_rx) = channel::<Option<String>>();
 
+        // Milliseconds since `started` at which output or input last happened,
+        // and why the recording was stopped early, if it was.
+        let last_activity = Arc::new(AtomicU64::new(0));
+        let stop_reason: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let (terminate_tx, terminate_rx) = channel::<()>();
+
         // On Windows, use ReadFile directly on the stdin handle instead of
         // std::io::stdin() (which uses ReadConsoleW internally). When raw mode is
         // active (ENABLE_LINE_INPUT and ENABLE_PROCESSED_INPUT both disabled),
@@ -137,14 +311,28 @@ impl Record {
                 .0 as isize
         };
 
+        // Once the recorded command exits, keystrokes are no longer forwarded to
+        // it but answer the save prompt instead.
+        let (key_tx, key_rx) = channel::<u8>();
+        let recording = Arc::new(AtomicBool::new(true));
+        let stdin_recording = recording.clone();
+        let stdin_activity = last_activity.clone();
+
         let record_stdin = self.stdin;
+        let invalid_utf8 = self.invalid_utf8;
+        let input_encoding = self.input_encoding;
+        if let Some(encoding) = input_encoding {
+            trace!("transcoding child input/output as {}", encoding.name());
+        }
         let stdin_event_tx = event_tx.clone();
+        // Decoder for UTF-8 sequences split across read boundaries, mirroring
+        // the one used in the stdout thread, which flushes it when the session
+        // ends. Invalid input is always replaced: escaping it as `\xNN` text
+        // would send that text to the child as if it had been typed.
+        let input_decoder = Arc::new(Mutex::new(Utf8Decoder::new(InvalidUtf8Policy::Replace)));
+        let stdin_decoder = input_decoder.clone();
 
         thread::spawn(move || {
-            // Buffer for incomplete UTF-8 sequences split across read boundaries,
-            // mirroring the pending_bytes approach used in the stdout thread.
-            let mut pending_bytes: Vec<u8> = Vec::new();
-
             loop {
                 let mut buf = [0u8; 10];
 
@@ -178,45 +366,53 @@ impl Record {
                     }
                 };
 
-                if record_stdin {
-                    pending_bytes.extend_from_slice(&buf[..n]);
+                stdin_activity.store(started.elapsed().as_millis() as u64, Ordering::SeqCst);
 
-                    let valid_up_to = match std::str::from_utf8(&pending_bytes) {
-                        Ok(_) => pending_bytes.len(),
-                        Err(e) => e.valid_up_to(),
-                    };
+                if !stdin_recording.load(Ordering::SeqCst) {
+                    for &key in &buf[..n] {
+                        if key_tx.send(key).is_err() {
+                            return;
+                        }
+                    }
+                    continue;
+                }
+
+                if !record_stdin && input_encoding.is_none() {
+                    stdin_tx.send((buf.to_vec(), n)).unwrap();
+                    continue;
+                }
 
-                    if valid_up_to > 0 {
-                        let chars =
-                            std::str::from_utf8(&pending_bytes[..valid_up_to]).unwrap();
-                        let now = SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .expect("check your machine time");
-                        let ts = now.as_secs() as f64 + now.subsec_nanos() as f64 * 1e-9
-                            - record_start_time;
-                        let data = vec![
-                            LineItem::F64(ts),
-                            LineItem::String("i".to_string()),
-                            LineItem::String(chars.to_string()),
-                        ];
-                        let line = serde_json::to_string(&data).unwrap() + "\n";
+                let chars = stdin_decoder.lock().unwrap().decode(&buf[..n]);
+
+                if record_stdin {
+                    if !chars.is_empty() {
+                        let line = event_line(record_start_time, "i", &chars);
                         // .ok(): if the writer thread has already exited (session
                         // ended) we simply discard the event.
                         stdin_event_tx.send(Some(line)).ok();
-                        pending_bytes.drain(..valid_up_to);
                     } else {
                         trace!("stdin: buffering incomplete UTF-8 sequence");
                     }
                 }
 
-                stdin_tx.send((buf.to_vec(), n)).unwrap();
+                // The child expects its input in the legacy encoding.
+                let bytes = match input_encoding {
+                    Some(encoding) => encoding.encode(&chars),
+                    None => buf[..n].to_vec(),
+                };
+                if !bytes.is_empty() {
+                    let len = bytes.len();
+                    stdin_tx.send((bytes, len)).unwrap();
+                }
             }
         });
 
         // The stdout thread owns the remaining (non-cloned) event_tx so that the
         // writer thread's channel is closed when this thread exits.
         let stdout_event_tx = event_tx;
-        let filename = self.filename.clone();
+        let stdout_activity = last_activity.clone();
+        let stdout_stop_reason = stop_reason.clone();
+        let mut limiter = OutputLimiter::new(self.max_output_bytes, self.max_output_rate);
 
         thread::spawn(move || {
             // Use raw Windows handle to write bytes directly, bypassing Rust's UTF-8 validation
@@ -225,77 +421,126 @@ impl Record {
             let stdout_handle: HANDLE =
                 unsafe { GetStdHandle(STD_OUTPUT_HANDLE).expect("failed to get stdout handle") };
 
-            // Buffer for incomplete UTF-8 sequences split across chunk boundaries
-            let mut pending_bytes: Vec<u8> = Vec::new();
+            // Decoder for sequences split across chunk boundaries, transcoding
+            // from the legacy input encoding if one was given
+            let mut decoder = OutputDecoder::new(input_encoding, invalid_utf8);
+
+            let emit = |limiter: &mut OutputLimiter, chars: &str| {
+                if chars.is_empty() {
+                    return;
+                }
+
+                for (code, data) in limiter.admit(chars, Instant::now()) {
+                    let line = event_line(record_start_time, code, &data);
+                    stdout_event_tx.send(Some(line)).ok();
+                }
+
+                // Write to console using WriteConsoleW for proper Unicode support
+                #[cfg(windows)]
+                unsafe {
+                    let utf16: Vec<u16> = chars.encode_utf16().collect();
+                    WriteConsoleW(stdout_handle, &utf16, None, None)
+                        .expect("failed to write stdout");
+                }
+            };
 
             loop {
-                let rv = stdout_rx.recv();
-                match rv {
-                    Ok((buf, len)) => {
-                        if len == 0 {
-                            trace!("stdout received close indicator");
-                            println!("Record finished. Result saved to file {}", filename);
-                            // Signal the writer thread that recording is done.
-                            stdout_event_tx.send(None).ok();
-                            break;
-                        }
+                // While an incomplete sequence is held back, only wait a bounded
+                // time for the rest of it so invalid output can't stall the cast.
+                let rv = if decoder.has_pending() {
+                    stdout_rx.recv_timeout(PENDING_FLUSH_TIMEOUT)
+                } else {
+                    stdout_rx.recv().map_err(RecvTimeoutError::from)
+                };
 
-                        let now = SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .expect("check your machine time");
-
-                        let ts = now.as_secs() as f64 + now.subsec_nanos() as f64 * 1e-9
-                            - record_start_time;
-
-                        // Combine pending bytes with new data
-                        pending_bytes.extend_from_slice(&buf[..len]);
-
-                        // Find the last valid UTF-8 boundary
-                        let valid_up_to = match std::str::from_utf8(&pending_bytes) {
-                            Ok(_) => pending_bytes.len(),
-                            Err(e) => e.valid_up_to(),
-                        };
-
-                        // Only process complete UTF-8 sequences
-                        if valid_up_to > 0 {
-                            // Safe: we just validated these bytes are valid UTF-8
-                            let chars =
-                                std::str::from_utf8(&pending_bytes[..valid_up_to]).unwrap();
-
-                            // https://github.com/asciinema/asciinema/blob/5a385765f050e04523c9d74fbf98d5afaa2deff0/asciinema/asciicast/v2.py#L119
-                            let data = vec![
-                                LineItem::F64(ts),
-                                LineItem::String("o".to_string()),
-                                LineItem::String(chars.to_string()),
-                            ];
-                            let line = serde_json::to_string(&data).unwrap() + "\n";
+                let chars = match rv {
+                    Ok((_, 0)) => {
+                        trace!("stdout received close indicator");
+                        emit(&mut limiter, &decoder.flush());
+                        // Input held back when the session ended won't be
+                        // completed any more.
+                        let input = input_decoder.lock().unwrap().flush();
+                        if record_stdin && !input.is_empty() {
+                            let line = event_line(record_start_time, "i", &input);
                             stdout_event_tx.send(Some(line)).ok();
-
-                            // Write to console using WriteConsoleW for proper Unicode support
-                            #[cfg(windows)]
-                            unsafe {
-                                let utf16: Vec<u16> = chars.encode_utf16().collect();
-                                WriteConsoleW(stdout_handle, &utf16, None, None)
-                                    .expect("failed to write stdout");
-                            }
                         }
-
-                        // Keep incomplete bytes for next iteration
-                        pending_bytes.drain(..valid_up_to);
+                        if let Some((code, data)) = limiter.finish() {
+                            let line = event_line(record_start_time, code, &data);
+                            stdout_event_tx.send(Some(line)).ok();
+                        }
+                        if let Some(reason) = stdout_stop_reason.lock().unwrap().take() {
+                            // Leave a final marker explaining the early stop.
+                            let line = event_line(record_start_time, "m", &reason);
+                            stdout_event_tx.send(Some(line)).ok();
+                            println!("\nRecording stopped: {}", reason);
+                        }
+                        // Signal the writer thread that recording is done.
+                        stdout_event_tx.send(None).ok();
+                        break;
+                    }
+                    Ok((buf, len)) => {
+                        stdout_activity
+                            .store(started.elapsed().as_millis() as u64, Ordering::SeqCst);
+                        decoder.decode(&buf[..len])
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        trace!("stdout: flushing incomplete sequence");
+                        decoder.flush()
                     }
-
                     Err(err) => {
-                        error!("reading stdout: {}", err.to_string());
+                        error!("reading stdout: {}", err);
                         break;
                     }
-                }
+                };
+
+                emit(&mut limiter, &chars);
             }
         });
 
+        // Watchdog thread: terminates the recorded command once the maximum
+        // duration or idle time is reached.
+        if self.max_duration.is_some() || self.stop_after_idle.is_some() {
+            let (max_duration, stop_after_idle) = (self.max_duration, self.stop_after_idle);
+            let recording = recording.clone();
+            thread::spawn(move || {
+                let reason = loop {
+                    if !recording.load(Ordering::SeqCst) {
+                        return;
+                    }
+
+                    let elapsed = started.elapsed();
+                    let idle = elapsed.saturating_sub(Duration::from_millis(
+                        last_activity.load(Ordering::SeqCst),
+                    ));
+
+                    // Sleep until the earliest limit could be reached, but wake
+                    // up regularly to notice the session ending on its own.
+                    let mut wait = Duration::from_secs(1);
+                    if let Some(max) = max_duration {
+                        if elapsed >= max {
+                            break format!("maximum duration of {} reached", format_duration(max));
+                        }
+                        wait = wait.min(max - elapsed);
+                    }
+                    if let Some(limit) = stop_after_idle {
+                        if idle >= limit {
+                            break format!("idle for {}", format_duration(limit));
+                        }
+                        wait = wait.min(limit - idle);
+                    }
+                    thread::sleep(wait);
+                };
+
+                trace!("stopping recording: {}", reason);
+                *stop_reason.lock().unwrap() = Some(reason);
+                terminate_tx.send(()).ok();
+            });
+        }
+
         // Dedicated writer thread: drains the event channel and writes lines to the
         // cast file in arrival order, eliminating races between the stdin/stdout threads.
         let output_writer = self.output_writer.clone();
-        thread::spawn(move || {
+        let writer = thread::spawn(move || {
             loop {
                 match event_rx.recv() {
                     Ok(Some(line)) => {
@@ -313,16 +558,49 @@ impl Record {
 
         #[cfg(windows)]
         {
+            let recording_to = std::path::absolute(&self.filename)
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|_| self.filename.clone());
+            self.terminal.set_env(RECORDING_ENV, &recording_to);
+
             self.terminal.attach_stdin(stdin_rx);
             self.terminal.attach_stdout(stdout_tx);
+            self.terminal.attach_terminate(terminate_rx);
             self.terminal.run(&self.command).unwrap();
+
+            recording.store(false, Ordering::SeqCst);
+            // Closing the pseudo console flushes the remaining output; wait for
+            // it to reach the cast file.
+            self.terminal.close();
+            writer.join().ok();
+            key_rx
         }
         #[cfg(not(windows))]
         {
             drop(stdin_rx);
             drop(stdout_tx);
+            drop(terminate_rx);
+            drop(recording);
+            drop(writer);
+            drop(key_rx);
             eprintln!("error: recording is only supported on Windows");
             std::process::exit(1);
         }
     }
 }
+
+/// Format an asciicast v2 event line, timestamped relative to `start_time`.
+fn event_line(start_time: f64, code: &str, data: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("check your machine time");
+    let ts = now.as_secs() as f64 + now.subsec_nanos() as f64 * 1e-9 - start_time;
+
+    // https://github.com/asciinema/asciinema/blob/5a385765f050e04523c9d74fbf98d5afaa2deff0/asciinema/asciicast/v2.py#L119
+    let data = vec![
+        LineItem::F64(ts),
+        LineItem::String(code.to_string()),
+        LineItem::String(data.to_string()),
+    ];
+    serde_json::to_string(&data).unwrap() + "\n"
+}
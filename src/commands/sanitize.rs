@@ -0,0 +1,161 @@
+use crate::commands::escape::{EscapeParser, Token};
+
+/// Final bytes of the CSI sequences let through: cursor movement, erasing,
+/// scrolling, insert/delete, tab stops, modes and SGR. Queries such as device
+/// status (`n`), device attributes (`c`) and window ops (`t`) are not.
+const ALLOWED_CSI: &str = "@ABCDEFGHIJKLMPSTXZ`abdefghlmrsu";
+
+/// Final bytes of the two-byte escape sequences let through: save/restore
+/// cursor, keypad modes, index, next line, tab set, reverse index and reset.
+const ALLOWED_ESCAPE: &str = "78=>DEHMc";
+
+/// DEC private modes that may be set or reset: auto-wrap, cursor visibility
+/// and the alternate screen. Others, such as mouse tracking, would change
+/// what the viewer's terminal sends as keystrokes.
+const ALLOWED_PRIVATE_MODES: [&str; 5] = ["7", "25", "47", "1047", "1049"];
+
+/// Removes the escape sequences a recording from an untrusted source could
+/// use against the viewer's terminal: clipboard access (OSC 52), titles,
+/// hyperlinks and other OSC strings, DCS/APC/PM strings, terminal queries
+/// whose answers would arrive as keystrokes, and C1 controls. Sequences on a
+/// small allowlist of screen drawing operations pass through unchanged.
+#[derive(Default)]
+pub(crate) struct EscapeFilter {
+    parser: EscapeParser,
+}
+
+impl EscapeFilter {
+    pub(crate) fn filter(&mut self, data: &str) -> String {
+        let mut out = String::with_capacity(data.len());
+        for c in data.chars() {
+            match self.parser.feed(c) {
+                // Bell, backspace, tab, line feed, vertical tab, form feed,
+                // carriage return and shift out/in.
+                Some(Token::Char(c @ '\x07'..='\x0f')) => out.push(c),
+                // Other C0 controls (e.g. ENQ, which asks for an answerback) and
+                // C1 controls, which some terminals take as 8-bit CSI or OSC.
+                Some(Token::Char('\x00'..='\x1f' | '\x7f'..='\u{9f}')) => {}
+                Some(Token::Char(c)) => out.push(c),
+                Some(Token::Escape(sequence)) if escape_allowed(&sequence) => {
+                    out.push_str(&sequence)
+                }
+                Some(Token::Csi(sequence)) if csi_allowed(&sequence) => out.push_str(&sequence),
+                _ => {}
+            }
+        }
+        out
+    }
+}
+
+/// Removes escape sequences and control characters from text the player
+/// shows itself, such as marker labels and titles, which are meant to be
+/// plain.
+pub(crate) fn plain_text(text: &str) -> String {
+    let mut parser = EscapeParser::default();
+    text.chars()
+        .filter_map(|c| match parser.feed(c) {
+            Some(Token::Char(c)) if !c.is_control() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a complete escape sequence (starting with ESC) is on the
+/// allowlist. Those with intermediate bytes designate charsets, set DEC line
+/// attributes and the like, and are let through.
+fn escape_allowed(sequence: &str) -> bool {
+    sequence.len() > 2 || ALLOWED_ESCAPE.contains(&sequence[1..])
+}
+
+/// Whether a complete CSI sequence (starting with `ESC [`) is on the
+/// allowlist. Sequences with intermediate bytes are requests or settings
+/// beyond drawing, except for setting the cursor style (`CSI Ps SP q`).
+/// Those with private parameters (starting with `<`, `=`, `>` or `?`) are
+/// queries or keyboard and mouse settings, except for setting DEC private
+/// modes on their own allowlist.
+fn csi_allowed(sequence: &str) -> bool {
+    let body = &sequence[2..];
+    let (rest, last) = body.split_at(body.len() - 1);
+    let intermediates = rest.trim_start_matches(|c| ('\x30'..='\x3f').contains(&c));
+    match intermediates {
+        "" if rest.starts_with(['<', '=', '>', '?']) => {
+            matches!(last, "h" | "l")
+                && rest.strip_prefix('?').is_some_and(|modes| {
+                    modes
+                        .split(';')
+                        .all(|mode| ALLOWED_PRIVATE_MODES.contains(&mode))
+                })
+        }
+        "" => ALLOWED_CSI.contains(last),
+        " " => last == "q",
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EscapeFilter, plain_text};
+
+    #[test]
+    fn test_filter_allows_drawing() {
+        let mut filter = EscapeFilter::default();
+        let data = "\x1b[2J\x1b[H\x1b[1;32mok\x1b[0m\r\n\x1b[?25l\x1b[?1049;7h\x1b[5;10r\x1b(B\x1b7\x1b8\x1b[2 q\t\x08";
+        assert_eq!(filter.filter(data), data);
+    }
+
+    #[test]
+    fn test_filter_strips_unsafe() {
+        let mut filter = EscapeFilter::default();
+        // Clipboard write, title, hyperlink, DCS and APC strings.
+        assert_eq!(filter.filter("a\x1b]52;c;ZXZpbA==\x07b"), "ab");
+        assert_eq!(filter.filter("\x1b]0;title\x1b\\c"), "c");
+        assert_eq!(
+            filter.filter("\x1b]8;;https://evil.example\x1b\\link\x1b]8;;\x1b\\"),
+            "link"
+        );
+        assert_eq!(filter.filter("\x1bP$qm\x1b\\\x1b_apc\x1b\\d"), "d");
+        // Queries: device status, device attributes, window ops, DECRQM, DECID, ENQ.
+        assert_eq!(
+            filter.filter("\x1b[6n\x1b[c\x1b[>c\x1b[14t\x1b[?2004$p\x1bZ\x05e"),
+            "e"
+        );
+        // Mouse tracking, bracketed paste and focus events.
+        assert_eq!(
+            filter.filter("\x1b[?1000h\x1b[?1002;1006h\x1b[?25;1003hg\x1b[?2004h\x1b[?1004l"),
+            "g"
+        );
+        // Kitty keyboard flags query and settings, modifyOtherKeys and
+        // XTRESTORE, which could turn mouse tracking back on.
+        assert_eq!(
+            filter.filter("\x1b[?u\x1b[>1u\x1b[=5u\x1b[<u\x1b[>4;2m\x1b[?1000rh\x1b[>1h"),
+            "h"
+        );
+        // C1 CSI and OSC.
+        assert_eq!(filter.filter("\u{9b}6n\u{9d}0;x\u{9c}f"), "6n0;xf");
+    }
+
+    #[test]
+    fn test_filter_across_events() {
+        let mut filter = EscapeFilter::default();
+        assert_eq!(filter.filter("a\x1b"), "a");
+        assert_eq!(filter.filter("[3"), "");
+        assert_eq!(filter.filter("1mb\x1b]52;c;"), "\x1b[31mb");
+        assert_eq!(filter.filter("ZXZpbA==\x07c"), "c");
+    }
+
+    #[test]
+    fn test_filter_long_csi() {
+        let mut filter = EscapeFilter::default();
+        let data = format!("\x1b[{}mok", "1;".repeat(100));
+        assert_eq!(filter.filter(&data), "ok");
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(plain_text("Setup"), "Setup");
+        assert_eq!(
+            plain_text("\x1b]52;c;ZXZpbA==\x07Demo\x1b[2J\u{9b}6n\r\n"),
+            "Demo6n"
+        );
+    }
+}
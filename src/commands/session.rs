@@ -0,0 +1,550 @@
+use crate::commands::duration::parse_timestamp;
+use crate::commands::fetch::{FetchOptions, fetch};
+use crate::commands::types::{LineItem, RecordHeader, SessionLine, V1Recording};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+use std::process::exit;
+use std::sync::mpsc::Receiver;
+
+/// Why a recording couldn't be read. Event errors carry the line they were
+/// found on, counting the header as line 1.
+#[derive(Debug)]
+pub(crate) enum CastError {
+    /// The recording couldn't be opened or read.
+    Io {
+        line: Option<usize>,
+        error: io::Error,
+    },
+    NotFound,
+    Fetch(String),
+    Empty,
+    UnsupportedVersion(u8),
+    /// Neither a v2 header nor a v1 recording.
+    InvalidFormat(serde_json::Error),
+    /// A v2 event line that isn't a `[time, code, data]` array.
+    InvalidEvent {
+        line: usize,
+        reason: String,
+    },
+}
+
+impl CastError {
+    /// Whether reading can go on past this error by skipping the line.
+    pub(crate) fn is_skippable(&self) -> bool {
+        matches!(self, CastError::InvalidEvent { .. })
+    }
+}
+
+impl fmt::Display for CastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CastError::Io {
+                line: Some(line),
+                error,
+            } => write!(f, "line {}: read error: {}", line, error),
+            CastError::Io { line: None, error } => write!(f, "{}", error),
+            CastError::NotFound => write!(f, "session does not exist"),
+            CastError::Fetch(reason) => write!(f, "failed to fetch: {}", reason),
+            CastError::Empty => write!(f, "file is empty"),
+            CastError::UnsupportedVersion(version) => {
+                write!(f, "unsupported file format version {}", version)
+            }
+            CastError::InvalidFormat(e) => write!(f, "unsupported or corrupt session file: {}", e),
+            CastError::InvalidEvent { line, reason } => {
+                write!(f, "line {}: corrupt record: {}", line, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CastError {}
+
+enum SessionLineSource {
+    Lines(io::Lines<Box<dyn BufRead>>),
+    Vec(std::vec::IntoIter<SessionLine>),
+    /// Events of a live stream, as they arrive.
+    Live(Receiver<SessionLine>),
+}
+
+pub(crate) struct Session {
+    pub(crate) header: RecordHeader,
+    line_iter: SessionLineSource,
+    /// Number of the last line read from `line_iter`.
+    line: usize,
+}
+
+/// Yields the events of a session whose type is one of the given codes.
+pub(crate) struct EventIter(Session, &'static [&'static str]);
+
+impl Iterator for EventIter {
+    type Item = Result<SessionLine, CastError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = match &mut self.0.line_iter {
+                SessionLineSource::Vec(iter) => iter.next(),
+                SessionLineSource::Live(rx) => rx.recv().ok(),
+                SessionLineSource::Lines(iter) => loop {
+                    let line = iter.next()?;
+                    self.0.line += 1;
+                    let content = match line {
+                        Ok(l) => l,
+                        Err(error) => {
+                            return Some(Err(CastError::Io {
+                                line: Some(self.0.line),
+                                error,
+                            }));
+                        }
+                    };
+
+                    // Skip empty or whitespace-only lines (e.g. trailing newlines in files)
+                    if content.trim().is_empty() {
+                        continue;
+                    }
+
+                    match parse_event(&content) {
+                        Ok(session_line) => break Some(session_line),
+                        Err(reason) => {
+                            return Some(Err(CastError::InvalidEvent {
+                                line: self.0.line,
+                                reason,
+                            }));
+                        }
+                    }
+                },
+            };
+
+            match event {
+                // Skip input ("i") and any other event types that weren't asked for.
+                Some(line) if self.1.contains(&line.code.as_str()) => return Some(Ok(line)),
+                Some(_) => continue,
+                None => return None,
+            }
+        }
+    }
+}
+
+/// Parses a v2 event line, returning why it isn't one otherwise.
+fn parse_event(content: &str) -> Result<SessionLine, String> {
+    let line_data: Vec<LineItem> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    match line_data.as_slice() {
+        [
+            LineItem::F64(timestamp),
+            LineItem::String(code),
+            LineItem::String(data),
+        ] => Ok(SessionLine {
+            timestamp: *timestamp,
+            code: code.clone(),
+            content: data.clone(),
+        }),
+        [_, _, _] => Err("expected [number, string, string]".to_string()),
+        _ => Err(format!("expected 3 fields, got {}", line_data.len())),
+    }
+}
+
+pub(crate) fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Servers whose recording pages at `/a/<id>` have a `.cast` download next
+/// to them, besides the configured one.
+const KNOWN_SERVERS: &[&str] = &["https://asciinema.org"];
+
+/// Resolves a recording given by ID as `a:<id>` to its download URL on
+/// `server`, and a recording page on `server` or a known server to its
+/// download URL. Anything else, including existing files, is returned as is.
+pub fn resolve_source(source: &str, server: &str) -> String {
+    if Path::new(source).exists() {
+        return source.to_string();
+    }
+    if let Some(id) = source.strip_prefix("a:") {
+        return format!("{}/a/{}.cast", server.trim_end_matches('/'), id);
+    }
+    if is_url(source) {
+        let mut servers = KNOWN_SERVERS.to_vec();
+        servers.push(server);
+        return normalize_url(source, &servers);
+    }
+    source.to_string()
+}
+
+/// Returns the host (and port) of a URL.
+fn url_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://")?.1;
+    rest.split(['/', '?', '#']).next()
+}
+
+/// Normalize a recording page URL on one of `servers` to its raw `.cast`
+/// download URL. For example, `https://asciinema.org/a/abc123` becomes
+/// `https://asciinema.org/a/abc123.cast`.
+/// Query strings and fragments are preserved (e.g. `?t=10` stays after `.cast`).
+pub(crate) fn normalize_url(url: &str, servers: &[&str]) -> String {
+    // Split off fragment, preserving the leading '#'
+    let (before_fragment, fragment) = match url.find('#') {
+        Some(idx) => (&url[..idx], &url[idx..]),
+        None => (url, ""),
+    };
+
+    // Split off query, preserving the leading '?'
+    let (mut main, query) = match before_fragment.find('?') {
+        Some(idx) => (&before_fragment[..idx], &before_fragment[idx..]),
+        None => (before_fragment, ""),
+    };
+
+    // Only normalize recording URLs on the given servers, or their subdomains
+    let on_server = url_host(main).is_some_and(|host| {
+        servers
+            .iter()
+            .filter_map(|server| url_host(server))
+            .any(|server| {
+                host == server
+                    || host
+                        .strip_suffix(server)
+                        .is_some_and(|sub| sub.ends_with('.'))
+            })
+    });
+    let is_recording = main
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split_once('/'))
+        .is_some_and(|(_, path)| path.starts_with("a/"));
+    if on_server && is_recording {
+        // Remove a trailing slash from the path, if present
+        if main.ends_with('/') {
+            main = &main[..main.len() - 1];
+        }
+
+        let mut normalized = main.to_string();
+        if !normalized.ends_with(".cast") {
+            normalized.push_str(".cast");
+        }
+
+        // Reattach query and fragment in their original order
+        normalized.push_str(query);
+        normalized.push_str(fragment);
+        normalized
+    } else {
+        // Other URLs are returned unchanged
+        url.to_string()
+    }
+}
+
+/// Read the start position from the `t` query parameter of a recording URL,
+/// e.g. `https://asciinema.org/a/abc123?t=1:23`.
+pub(crate) fn start_at_from_url(url: &str) -> Option<f64> {
+    let query = url.split('#').next()?.split_once('?')?.1;
+    query
+        .split('&')
+        .filter_map(|param| param.strip_prefix("t="))
+        .find_map(|t| parse_timestamp(&t.replace("%3A", ":").replace("%3a", ":")).ok())
+        .map(|t| t.as_secs_f64())
+}
+
+/// Parse a session from a buffered reader, detecting v2 or v1 format automatically.
+pub(crate) fn parse_reader(reader: Box<dyn BufRead>) -> Result<Session, CastError> {
+    let mut line_iter: io::Lines<Box<dyn BufRead>> = reader.lines();
+
+    let first_line = match line_iter.next() {
+        Some(line) => line.map_err(|error| CastError::Io {
+            line: Some(1),
+            error,
+        })?,
+        None => return Err(CastError::Empty),
+    };
+
+    if let Ok(header) = serde_json::from_str::<RecordHeader>(&first_line) {
+        // v2 format: header on first line, events stream on subsequent lines.
+        // Validate version == 2 to avoid misclassifying v1 recordings that
+        // happen to contain a timestamp field parseable as RecordHeader.
+        if header.version == 2 {
+            return Ok(Session {
+                header,
+                line_iter: SessionLineSource::Lines(line_iter),
+                line: 1,
+            });
+        }
+    }
+
+    // Try v1 format: entire content is a single JSON object.
+    // Collect remaining lines from the already-opened iterator.
+    let mut file_content = first_line;
+    for (i, line) in line_iter.enumerate() {
+        let line = line.map_err(|error| CastError::Io {
+            line: Some(i + 2),
+            error,
+        })?;
+        file_content.push('\n');
+        file_content.push_str(&line);
+    }
+    parse_v1(file_content)
+}
+
+fn parse_v1(file_content: String) -> Result<Session, CastError> {
+    let recording =
+        serde_json::from_str::<V1Recording>(&file_content).map_err(CastError::InvalidFormat)?;
+    if recording.version != 1 {
+        return Err(CastError::UnsupportedVersion(recording.version));
+    }
+
+    let header = RecordHeader {
+        version: recording.version,
+        width: recording.width,
+        height: recording.height,
+        timestamp: 0,
+        environment: HashMap::new(),
+        invalid_utf8: None,
+        idle_time_limit: None,
+        title: recording.title,
+        theme: None,
+    };
+
+    let mut absolute_time: f64 = 0.0;
+    let events: Vec<SessionLine> = recording
+        .stdout
+        .into_iter()
+        .map(|(delay, text)| {
+            absolute_time += delay;
+            SessionLine {
+                timestamp: absolute_time,
+                code: "o".to_string(),
+                content: text,
+            }
+        })
+        .collect();
+
+    Ok(Session {
+        header,
+        line_iter: SessionLineSource::Vec(events.into_iter()),
+        line: 0,
+    })
+}
+
+impl Session {
+    pub(crate) fn new(source: &str, fetch: &FetchOptions) -> Result<Self, CastError> {
+        if source == "-" {
+            Self::from_stdin()
+        } else if is_url(source) {
+            Self::from_url(source, fetch)
+        } else {
+            Self::from_file(source)
+        }
+    }
+
+    /// Like `new`, but prints the error and exits if the session can't be
+    /// read.
+    pub(crate) fn open_or_exit(source: &str, fetch: &FetchOptions) -> Self {
+        Self::new(source, fetch).unwrap_or_else(|e| {
+            eprintln!("'{}': {}", source, e);
+            exit(1);
+        })
+    }
+
+    fn from_stdin() -> Result<Self, CastError> {
+        parse_reader(Box::new(io::BufReader::new(io::stdin())))
+    }
+
+    fn from_file(filename: &str) -> Result<Self, CastError> {
+        if !Path::new(filename).exists() {
+            return Err(CastError::NotFound);
+        }
+
+        let file = File::open(filename).map_err(|error| CastError::Io { line: None, error })?;
+        parse_reader(Box::new(io::BufReader::new(file)))
+    }
+
+    fn from_url(url: &str, options: &FetchOptions) -> Result<Self, CastError> {
+        let data = fetch(&normalize_url(url, KNOWN_SERVERS), options).map_err(CastError::Fetch)?;
+        parse_reader(Box::new(io::Cursor::new(data)))
+    }
+
+    /// A live session whose events are sent on `events` as they arrive.
+    pub(crate) fn live(header: RecordHeader, events: Receiver<SessionLine>) -> Self {
+        Session {
+            header,
+            line_iter: SessionLineSource::Live(events),
+            line: 0,
+        }
+    }
+
+    /// Returns the channel the events of a live session arrive on.
+    pub(crate) fn into_live(self) -> Option<Receiver<SessionLine>> {
+        match self.line_iter {
+            SessionLineSource::Live(events) => Some(events),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn event_iter(self, codes: &'static [&'static str]) -> EventIter {
+        EventIter(self, codes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CastError, KNOWN_SERVERS, is_url, normalize_url, parse_reader, resolve_source,
+        start_at_from_url,
+    };
+    use std::io::Cursor;
+
+    const HEADER: &str = r#"{"version": 2, "width": 80, "height": 24, "timestamp": 0, "env": {}}"#;
+
+    fn parse(data: &str) -> Result<super::Session, CastError> {
+        parse_reader(Box::new(Cursor::new(data.to_string())))
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://asciinema.org/a/abc123"));
+        assert!(is_url("http://example.com/recording.cast"));
+        assert!(!is_url("my_recording.cast"));
+        assert!(!is_url("/path/to/recording.cast"));
+        assert!(!is_url("C:\\recordings\\session.cast"));
+    }
+
+    #[test]
+    fn test_start_at_from_url() {
+        assert_eq!(
+            start_at_from_url("https://asciinema.org/a/abc123?t=10"),
+            Some(10.0)
+        );
+        assert_eq!(
+            start_at_from_url("https://asciinema.org/a/abc123?t=1:23"),
+            Some(83.0)
+        );
+        assert_eq!(
+            start_at_from_url("https://asciinema.org/a/abc123.cast?speed=2&t=1%3A23#intro"),
+            Some(83.0)
+        );
+        assert_eq!(
+            start_at_from_url("https://asciinema.org/a/abc123#t=10"),
+            None
+        );
+        assert_eq!(start_at_from_url("https://asciinema.org/a/abc123"), None);
+    }
+
+    #[test]
+    fn test_normalize_url_asciinema() {
+        // Basic recording URL
+        assert_eq!(
+            normalize_url("https://asciinema.org/a/abc123", KNOWN_SERVERS),
+            "https://asciinema.org/a/abc123.cast"
+        );
+        // Already has .cast – should not double-append
+        assert_eq!(
+            normalize_url("https://asciinema.org/a/abc123.cast", KNOWN_SERVERS),
+            "https://asciinema.org/a/abc123.cast"
+        );
+        // Non-asciinema URL – should be returned unchanged
+        assert_eq!(
+            normalize_url("https://example.com/recording.cast", KNOWN_SERVERS),
+            "https://example.com/recording.cast"
+        );
+        // URL with query string – .cast inserted before '?'
+        assert_eq!(
+            normalize_url("https://asciinema.org/a/abc123?t=10", KNOWN_SERVERS),
+            "https://asciinema.org/a/abc123.cast?t=10"
+        );
+        // URL with trailing slash – slash stripped before appending .cast
+        assert_eq!(
+            normalize_url("https://asciinema.org/a/abc123/", KNOWN_SERVERS),
+            "https://asciinema.org/a/abc123.cast"
+        );
+        // URL with fragment – .cast inserted before '#'
+        assert_eq!(
+            normalize_url("https://asciinema.org/a/abc123#intro", KNOWN_SERVERS),
+            "https://asciinema.org/a/abc123.cast#intro"
+        );
+        // URL with both query string and fragment
+        assert_eq!(
+            normalize_url("https://asciinema.org/a/abc123?t=10#intro", KNOWN_SERVERS),
+            "https://asciinema.org/a/abc123.cast?t=10#intro"
+        );
+    }
+
+    #[test]
+    fn test_normalize_url_servers() {
+        let servers = ["https://asciinema.org", "http://casts.example.com:4000/"];
+        assert_eq!(
+            normalize_url("http://casts.example.com:4000/a/42?t=5", &servers),
+            "http://casts.example.com:4000/a/42.cast?t=5"
+        );
+        assert_eq!(
+            normalize_url("https://demo.asciinema.org/a/abc", &servers),
+            "https://demo.asciinema.org/a/abc.cast"
+        );
+        // Same path on another server, or another path on a known one.
+        assert_eq!(
+            normalize_url("https://example.com/a/42", &servers),
+            "https://example.com/a/42"
+        );
+        assert_eq!(
+            normalize_url("https://notasciinema.org/a/42", &servers),
+            "https://notasciinema.org/a/42"
+        );
+        assert_eq!(
+            normalize_url("https://asciinema.org/explore/a/42", &servers),
+            "https://asciinema.org/explore/a/42"
+        );
+    }
+
+    #[test]
+    fn test_resolve_source() {
+        let server = "https://casts.example.com/";
+        assert_eq!(
+            resolve_source("a:abc123", server),
+            "https://casts.example.com/a/abc123.cast"
+        );
+        assert_eq!(
+            resolve_source("https://casts.example.com/a/abc123", server),
+            "https://casts.example.com/a/abc123.cast"
+        );
+        assert_eq!(
+            resolve_source("https://asciinema.org/a/xyz", server),
+            "https://asciinema.org/a/xyz.cast"
+        );
+        assert_eq!(resolve_source("demo.cast", server), "demo.cast");
+        assert_eq!(resolve_source("-", server), "-");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(parse(""), Err(CastError::Empty)));
+        assert!(matches!(
+            parse("not json"),
+            Err(CastError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 3, "width": 80, "height": 24}"#),
+            Err(CastError::UnsupportedVersion(3))
+        ));
+    }
+
+    #[test]
+    fn test_event_errors() {
+        let data = format!(
+            "{}\n[0.1, \"o\", \"a\"]\n\n[0.2, \"o\"]\n{{}}\n[0.3, 1, \"b\"]\n[0.4, \"o\", \"c\"]\n",
+            HEADER
+        );
+        let events: Vec<_> = parse(&data).unwrap().event_iter(&["o"]).collect();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0].as_ref().unwrap().content, "a");
+        let lines: Vec<usize> = events[1..4]
+            .iter()
+            .map(|event| match event {
+                Err(CastError::InvalidEvent { line, .. }) => *line,
+                _ => panic!("expected an invalid event"),
+            })
+            .collect();
+        assert_eq!(lines, [4, 5, 6]);
+        assert_eq!(
+            events[1].as_ref().err().unwrap().to_string(),
+            "line 4: corrupt record: expected 3 fields, got 2"
+        );
+        assert_eq!(events[4].as_ref().unwrap().content, "c");
+    }
+}
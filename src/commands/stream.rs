@@ -22,6 +22,8 @@ use windows::Win32::{
     System::Console::{GetStdHandle, WriteConsoleW, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE},
 };
 
+#[cfg(windows)]
+use crate::commands::record::RECORDING_ENV;
 #[cfg(windows)]
 use crate::commands::types::LineItem;
 #[cfg(windows)]
@@ -260,6 +262,7 @@ impl Stream {
             }
         });
 
+        self.terminal.set_env(RECORDING_ENV, &self.stream_url);
         self.terminal.attach_stdin(stdin_rx);
         self.terminal.attach_stdout(stdout_tx);
         self.terminal.run(&self.command).unwrap();
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::commands::encoding::InvalidUtf8Policy;
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct RecordHeader {
     pub(crate) version: u8,
@@ -9,6 +11,24 @@ pub(crate) struct RecordHeader {
     pub(crate) timestamp: u64,
     #[serde(rename = "env")]
     pub(crate) environment: HashMap<String, String>,
+    /// Policy used while recording for output that was not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) invalid_utf8: Option<InvalidUtf8Policy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) idle_time_limit: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) theme: Option<Theme>,
+}
+
+/// Terminal colors of a recording, as `#rrggbb` values. `palette` holds 8 or
+/// 16 colors separated by colons.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Theme {
+    pub(crate) fg: String,
+    pub(crate) bg: String,
+    pub(crate) palette: String,
 }
 
 /// Represents an asciinema v1 format recording (entire file is one JSON object).
@@ -19,13 +39,16 @@ pub(crate) struct V1Recording {
     pub(crate) width: i16,
     pub(crate) height: i16,
     #[serde(default)]
+    pub(crate) title: Option<String>,
+    #[serde(default)]
     pub(crate) stdout: Vec<(f64, String)>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct SessionLine {
     pub(crate) timestamp: f64,
-    pub(crate) stdout: bool,
+    /// Event type: "o" for output, "i" for input, "m" for markers, ...
+    pub(crate) code: String,
     pub(crate) content: String,
 }
 
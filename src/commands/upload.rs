@@ -20,10 +20,12 @@ impl Upload {
         }
     }
 
-    pub fn execute(&self) {
-        match self.api_service.upload(&self.filepath) {
-            Some(result_url) => println!("Result Url: {}", result_url),
-            _ => {}
+    /// Upload the session, returning the result URL on success.
+    pub fn execute(&self) -> Option<String> {
+        let result_url = self.api_service.upload(&self.filepath);
+        if let Some(url) = &result_url {
+            println!("Result Url: {}", url);
         }
+        result_url
     }
 }
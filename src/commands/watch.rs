@@ -0,0 +1,291 @@
+use crate::commands::play::Play;
+use crate::commands::session::Session;
+use crate::commands::types::{LineItem, RecordHeader, SessionLine};
+
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::process::exit;
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+use std::time::Instant;
+
+use log::{error, trace};
+use serde::Deserialize;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::HeaderValue;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+/// WebSocket subprotocol asking the server for asciicast v2 encoded events.
+const SUBPROTOCOL: &str = "v2.asciicast";
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Sent by the server when a viewer connects and whenever the stream is reset,
+/// e.g. because the producer reconnected.
+#[derive(Deserialize)]
+struct StreamHeader {
+    width: i16,
+    height: i16,
+    /// Snapshot of the screen when the viewer joined, if the server sends one.
+    #[serde(default)]
+    init: Option<String>,
+}
+
+/// Watches a live stream in the terminal.
+pub struct Watch {
+    ws_url: String,
+}
+
+impl Watch {
+    pub fn new(ws_url: String) -> Self {
+        Watch { ws_url }
+    }
+
+    pub fn execute(self) {
+        let session = connect(&self.ws_url);
+        Play::live(session).execute();
+        println!("Stream ended.");
+    }
+}
+
+/// Returns the viewer WebSocket URL for a stream given as a `ws(s)://` URL or
+/// as its `http(s)://<server>/s/<id>` page. Returns `None` for anything else,
+/// which is taken as a stream ID on the configured server.
+pub fn stream_ws_url(target: &str) -> Option<String> {
+    if target.starts_with("ws://") || target.starts_with("wss://") {
+        return Some(target.to_string());
+    }
+
+    let (scheme, rest) = match target.split_once("://")? {
+        ("https", rest) => ("wss", rest),
+        ("http", rest) => ("ws", rest),
+        _ => return None,
+    };
+    let (host, id) = rest.trim_end_matches('/').split_once("/s/")?;
+    Some(format!("{}://{}/ws/s/{}", scheme, host, id))
+}
+
+/// Connects to the viewer WebSocket at `url` and returns a live session fed
+/// by its events.
+fn connect(url: &str) -> Session {
+    let mut request = url.into_client_request().unwrap_or_else(|e| {
+        eprintln!("invalid stream URL {}: {}", url, e);
+        exit(1);
+    });
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static(SUBPROTOCOL),
+    );
+
+    let (mut ws, _) = tungstenite::connect(request).unwrap_or_else(|e| {
+        eprintln!("failed to connect to stream {}: {}", url, e);
+        exit(1);
+    });
+
+    let started = Instant::now();
+    let (tx, rx) = channel();
+
+    // The stream starts with a header giving the terminal size.
+    let header = match read_text(&mut ws) {
+        Some(text) => match serde_json::from_str::<StreamHeader>(&text) {
+            Ok(header) => {
+                send_snapshot(&tx, &header, started);
+                header
+            }
+            Err(_) => {
+                // No header; assume the default terminal size.
+                send_event(&tx, &text, started);
+                StreamHeader {
+                    width: 80,
+                    height: 24,
+                    init: None,
+                }
+            }
+        },
+        None => {
+            eprintln!("stream {} closed before sending anything", url);
+            exit(1);
+        }
+    };
+
+    thread::spawn(move || {
+        while let Some(text) = read_text(&mut ws) {
+            match serde_json::from_str::<StreamHeader>(&text) {
+                // The stream was reset: start over at the new size.
+                Ok(header) => {
+                    let size = format!("{}x{}", header.width, header.height);
+                    let _ = tx.send(live_event(started, "r", size));
+                    send_snapshot(&tx, &header, started);
+                }
+                Err(_) => send_event(&tx, &text, started),
+            }
+        }
+    });
+
+    Session::live(
+        RecordHeader {
+            version: 2,
+            width: header.width,
+            height: header.height,
+            timestamp: 0,
+            environment: HashMap::new(),
+            invalid_utf8: None,
+            idle_time_limit: None,
+            title: None,
+            theme: None,
+        },
+        rx,
+    )
+}
+
+/// Reads the next text message, skipping control frames. Returns `None`
+/// once the stream is closed.
+fn read_text(ws: &mut Socket) -> Option<String> {
+    loop {
+        match ws.read() {
+            Ok(Message::Text(text)) => return Some(text.to_string()),
+            Ok(Message::Close(frame)) => {
+                trace!("server closed stream: {:?}", frame);
+                return None;
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::AlreadyClosed | tungstenite::Error::ConnectionClosed) => {
+                return None;
+            }
+            Err(e) => {
+                error!("websocket read error: {}", e);
+                return None;
+            }
+        }
+    }
+}
+
+fn send_snapshot(tx: &Sender<SessionLine>, header: &StreamHeader, started: Instant) {
+    if let Some(init) = &header.init {
+        let _ = tx.send(live_event(started, "o", init.clone()));
+    }
+}
+
+/// Forwards an asciicast event line, ignoring anything malformed.
+fn send_event(tx: &Sender<SessionLine>, text: &str, started: Instant) {
+    match serde_json::from_str::<Vec<LineItem>>(text).as_deref() {
+        Ok(
+            [
+                LineItem::F64(_),
+                LineItem::String(code),
+                LineItem::String(data),
+            ],
+        ) => {
+            let _ = tx.send(live_event(started, code, data.clone()));
+        }
+        _ => trace!("ignoring unexpected stream message: {}", text),
+    }
+}
+
+/// An event stamped with its arrival time, which is what live playback goes by.
+fn live_event(started: Instant, code: &str, content: String) -> SessionLine {
+    SessionLine {
+        timestamp: started.elapsed().as_secs_f64(),
+        code: code.to_string(),
+        content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SUBPROTOCOL, Watch, connect, stream_ws_url};
+    use std::net::TcpListener;
+    use std::thread;
+    use tungstenite::Message;
+    use tungstenite::handshake::server::{Request, Response};
+    use tungstenite::http::HeaderValue;
+
+    /// Starts a stand-in stream server that sends `messages` to the first
+    /// viewer and then closes the stream. Returns its WebSocket URL.
+    #[allow(clippy::result_large_err)]
+    fn serve(messages: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/ws/s/test", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept_hdr(stream, |_: &Request, mut response: Response| {
+                response.headers_mut().insert(
+                    "Sec-WebSocket-Protocol",
+                    HeaderValue::from_static(SUBPROTOCOL),
+                );
+                Ok(response)
+            })
+            .unwrap();
+            for message in messages {
+                ws.send(Message::Text((*message).into())).unwrap();
+            }
+            ws.close(None).unwrap();
+            // Wait for the viewer to acknowledge the close.
+            while ws.read().is_ok() {}
+        });
+        url
+    }
+
+    #[test]
+    fn test_stream_ws_url() {
+        assert_eq!(
+            stream_ws_url("wss://asciinema.org/ws/s/abc").as_deref(),
+            Some("wss://asciinema.org/ws/s/abc")
+        );
+        assert_eq!(
+            stream_ws_url("https://asciinema.org/s/abc").as_deref(),
+            Some("wss://asciinema.org/ws/s/abc")
+        );
+        assert_eq!(
+            stream_ws_url("http://localhost:4000/s/abc/").as_deref(),
+            Some("ws://localhost:4000/ws/s/abc")
+        );
+        assert_eq!(stream_ws_url("https://asciinema.org/a/abc"), None);
+        assert_eq!(stream_ws_url("abc"), None);
+    }
+
+    #[test]
+    fn test_connect() {
+        let url = serve(&[
+            r#"{"version":2,"width":100,"height":30,"init":"$ "}"#,
+            r#"[0.5,"o","ls\r\n"]"#,
+            "not json",
+            r#"[1.0,"r","120x40"]"#,
+            r#"{"version":2,"width":80,"height":24}"#,
+            r#"[0.1,"o","again"]"#,
+        ]);
+
+        let session = connect(&url);
+        assert_eq!((session.header.width, session.header.height), (100, 30));
+
+        let events: Vec<(String, String)> = session
+            .event_iter(&["o", "r"])
+            .map(|event| event.unwrap())
+            .map(|event| (event.code, event.content))
+            .collect();
+        let expected = [
+            ("o", "$ "),
+            ("o", "ls\r\n"),
+            ("r", "120x40"),
+            ("r", "80x24"),
+            ("o", "again"),
+        ];
+        assert_eq!(
+            events,
+            expected.map(|(code, data)| (code.to_string(), data.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let url = serve(&[
+            r#"{"version":2,"width":80,"height":24}"#,
+            r#"[0.1,"o","hello "]"#,
+            r#"[0.2,"m","chapter"]"#,
+            r#"[0.3,"o","world\r\n"]"#,
+        ]);
+        Watch::new(url).execute();
+    }
+}
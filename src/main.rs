@@ -7,10 +7,15 @@ mod terminal;
 
 use clap::builder::styling::{AnsiColor, Styles};
 use clap::{Arg, Command, crate_version};
-use commands::{Asciinema, Auth, Play, Stream};
-use commands::{ApiService, Record, Upload};
+use commands::{Asciinema, Auth, Cat, Play, Stream, Watch, read_playlist};
+use commands::{
+    ApiService, FetchOptions, InputEncoding, InvalidUtf8Policy, Record, Upload, check_nested,
+    parse_duration, parse_size, parse_timestamp, resolve_source, stream_ws_url,
+};
 use fern::colors::ColoredLevelConfig;
 use log::trace;
+use std::path::Path;
+use std::time::Duration;
 
 fn setup_logger(level: log::LevelFilter) -> Result<(), fern::InitError> {
     let colors = ColoredLevelConfig::new();
@@ -29,6 +34,28 @@ fn setup_logger(level: log::LevelFilter) -> Result<(), fern::InitError> {
     Ok(())
 }
 
+/// Resolves recording IDs (`a:<id>`) and recording page URLs against the
+/// configured server.
+fn resolve_recordings(sources: &[String]) -> Vec<String> {
+    let remote = |source: &String| {
+        source.starts_with("a:") || source.starts_with("http://") || source.starts_with("https://")
+    };
+    if !sources.iter().any(remote) {
+        return sources.to_vec();
+    }
+    let server = Asciinema::configured_server_url();
+    sources
+        .iter()
+        .map(|source| {
+            if remote(source) {
+                resolve_source(source, &server)
+            } else {
+                source.clone()
+            }
+        })
+        .collect()
+}
+
 fn main() {
     let styles = Styles::styled()
         .header(AnsiColor::Yellow.on_default().bold())
@@ -69,6 +96,67 @@ fn main() {
                         .help("Enable stdin (keyboard input) recording")
                         .num_args(0)
                         .long("stdin"),
+                )
+                .arg(
+                    Arg::new("invalid-utf8")
+                        .help("How to record output that is not valid UTF-8, can be one of [replace|escape|drop]")
+                        .long("invalid-utf8")
+                        .num_args(1)
+                        .default_value("replace")
+                        .value_parser(|s: &str| s.parse::<InvalidUtf8Policy>()),
+                )
+                .arg(
+                    Arg::new("input-encoding")
+                        .help("Code page of the recorded program, e.g. cp437, cp850, cp1252 or shift-jis")
+                        .long("input-encoding")
+                        .num_args(1)
+                        .value_parser(|s: &str| s.parse::<InputEncoding>()),
+                )
+                .arg(
+                    Arg::new("upload")
+                        .help("Upload the session to api server when the recording ends")
+                        .num_args(0)
+                        .long("upload"),
+                )
+                .arg(
+                    Arg::new("upload-delete")
+                        .help("Delete the local file after a successful upload")
+                        .num_args(0)
+                        .long("upload-delete"),
+                )
+                .arg(
+                    Arg::new("nested")
+                        .help("Record even if this session is already being recorded")
+                        .num_args(0)
+                        .long("nested"),
+                )
+                .arg(
+                    Arg::new("max-duration")
+                        .help("Stop recording after the given duration, e.g. 90s, 30m or 2h")
+                        .long("max-duration")
+                        .num_args(1)
+                        .value_parser(parse_duration),
+                )
+                .arg(
+                    Arg::new("stop-after-idle")
+                        .help("Stop recording after no output or input for the given duration")
+                        .long("stop-after-idle")
+                        .num_args(1)
+                        .value_parser(parse_duration),
+                )
+                .arg(
+                    Arg::new("max-output-bytes")
+                        .help("Stop recording output after the given size, e.g. 100MB or 1G; the command keeps running")
+                        .long("max-output-bytes")
+                        .num_args(1)
+                        .value_parser(parse_size),
+                )
+                .arg(
+                    Arg::new("max-output-rate")
+                        .help("Record at most the given size of output per second, e.g. 512K")
+                        .long("max-output-rate")
+                        .num_args(1)
+                        .value_parser(parse_size),
                 ),
         )
         .subcommand(
@@ -76,9 +164,28 @@ fn main() {
                 .about("Play a recorded session")
                 .arg(
                     Arg::new("file")
-                        .help("The record session file path or URL (e.g. https://asciinema.org/a/<id>)")
+                        .help("The record session file paths, URLs (e.g. https://asciinema.org/a/<id>) or IDs on the configured server (a:<id>), or - for stdin; several are played in turn")
                         .index(1)
-                        .required(true),
+                        .num_args(1..)
+                        .required_unless_present("playlist"),
+                )
+                .arg(
+                    Arg::new("playlist")
+                        .help("Also play the recordings listed in the given file, one per line")
+                        .long("playlist")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("status")
+                        .help("Show the position, length, speed and last marker in a status line")
+                        .num_args(0)
+                        .long("status"),
+                )
+                .arg(
+                    Arg::new("title-cards")
+                        .help("Show the title of each recording before playing it")
+                        .num_args(0)
+                        .long("title-cards"),
                 )
                 .arg(
                     Arg::new("idle-time-limit")
@@ -111,6 +218,108 @@ fn main() {
                                 }
                             })
                         }),
+                )
+                .arg(
+                    Arg::new("start-at")
+                        .help("Start playback at the given position, e.g. 83 or 1:23")
+                        .long("start-at")
+                        .num_args(1)
+                        .value_parser(parse_timestamp),
+                )
+                .arg(
+                    Arg::new("pause-on-markers")
+                        .help("Pause playback at each marker")
+                        .num_args(0)
+                        .long("pause-on-markers"),
+                )
+                .arg(
+                    Arg::new("show-input")
+                        .help("Show the keys typed during recording in a status line")
+                        .num_args(0)
+                        .long("show-input"),
+                )
+                .arg(
+                    Arg::new("resize")
+                        .help("Ask the terminal to resize to the recording's size, and back afterwards")
+                        .num_args(0)
+                        .long("resize"),
+                )
+                .arg(
+                    Arg::new("fit")
+                        .help("Crop recordings larger than the terminal to the part around the cursor")
+                        .num_args(0)
+                        .long("fit")
+                        .conflicts_with("resize"),
+                )
+                .arg(
+                    Arg::new("loop")
+                        .help("Play the session N times (--loop=N), or forever if N is omitted")
+                        .long("loop")
+                        .value_name("N")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("fetch-timeout")
+                        .help("Give up downloading a remote recording after the given duration")
+                        .long("fetch-timeout")
+                        .num_args(1)
+                        .default_value("30s")
+                        .value_parser(parse_duration),
+                )
+                .arg(
+                    Arg::new("max-download-size")
+                        .help("Refuse remote recordings larger than the given size, e.g. 64MB")
+                        .long("max-download-size")
+                        .num_args(1)
+                        .default_value("64MB")
+                        .value_parser(parse_size),
+                )
+                .arg(
+                    Arg::new("unsafe-raw")
+                        .help("Don't filter escape sequences out of remote recordings")
+                        .num_args(0)
+                        .long("unsafe-raw"),
+                )
+                .arg(
+                    Arg::new("lenient")
+                        .help("Skip corrupt lines in the recording with a warning")
+                        .num_args(0)
+                        .long("lenient"),
+                )
+                .arg(
+                    Arg::new("loop-gap")
+                        .help("Seconds to wait between loop iterations")
+                        .long("loop-gap")
+                        .num_args(1)
+                        .default_value("2")
+                        .value_parser(|s: &str| {
+                            s.parse::<f64>().map_err(|e| e.to_string()).and_then(|v| {
+                                if v >= 0.0 {
+                                    Ok(v)
+                                } else {
+                                    Err("loop-gap must be non-negative".to_string())
+                                }
+                            })
+                        }),
+                ),
+        )
+        .subcommand(
+            Command::new("cat")
+                .about("Print the recorded output of sessions without timing")
+                .arg(
+                    Arg::new("file")
+                        .help("The record session file paths, URLs or IDs (a:<id>), or - for stdin")
+                        .index(1)
+                        .num_args(1..)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("plain")
+                        .help("Strip ANSI escape sequences")
+                        .num_args(0)
+                        .long("plain"),
                 ),
         )
         .subcommand(
@@ -151,6 +360,22 @@ fn main() {
                         .help("Reconnect to an existing stream by its ID")
                         .num_args(1)
                         .long("id"),
+                )
+                .arg(
+                    Arg::new("nested")
+                        .help("Stream even if this session is already being recorded")
+                        .num_args(0)
+                        .long("nested"),
+                ),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Watch a live stream in the terminal")
+                .arg(
+                    Arg::new("stream")
+                        .help("The stream URL (e.g. https://asciinema.org/s/<id>) or ID")
+                        .index(1)
+                        .required(true),
                 ),
         )
         .arg(
@@ -182,26 +407,111 @@ fn main() {
 
     match m.subcommand() {
         Some(("play", play_matches)) => {
+            let mut sources: Vec<String> = play_matches
+                .get_many::<String>("file")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            // `--loop` only takes a count after `=`, so in `--loop 3 a.cast`
+            // the 3 would be read as a recording.
+            if play_matches.contains_id("loop")
+                && play_matches.get_one::<u64>("loop").is_none()
+                && let Some(count) = sources
+                    .iter()
+                    .find(|source| source.parse::<u64>().is_ok() && !Path::new(source).exists())
+            {
+                eprintln!(
+                    "'{}' is not a recording; use --loop={} to play {} times",
+                    count, count, count
+                );
+                std::process::exit(1);
+            }
+            if let Some(playlist) = play_matches.get_one::<String>("playlist") {
+                match read_playlist(playlist) {
+                    Ok(items) => sources.extend(items),
+                    Err(e) => {
+                        eprintln!("error reading playlist '{}': {}", playlist, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if sources.is_empty() {
+                eprintln!("nothing to play");
+                std::process::exit(1);
+            }
             let play = Play::new(
-                play_matches
-                    .get_one::<String>("file")
-                    .expect("record file required")
-                    .to_owned(),
+                resolve_recordings(&sources),
                 play_matches.get_one::<f64>("idle-time-limit").copied(),
                 play_matches.get_one::<f64>("speed").copied().unwrap_or(1.0),
+            )
+            .with_start_at(
+                play_matches
+                    .get_one::<Duration>("start-at")
+                    .map(Duration::as_secs_f64),
+            )
+            .with_pause_on_markers(play_matches.get_flag("pause-on-markers"))
+            .with_show_input(play_matches.get_flag("show-input"))
+            .with_resize(play_matches.get_flag("resize"))
+            .with_fit(play_matches.get_flag("fit"))
+            .with_lenient(play_matches.get_flag("lenient"))
+            .with_unsafe_raw(play_matches.get_flag("unsafe-raw"))
+            .with_title_cards(play_matches.get_flag("title-cards"))
+            .with_status(play_matches.get_flag("status"))
+            .with_fetch_options(FetchOptions {
+                timeout: *play_matches.get_one::<Duration>("fetch-timeout").unwrap(),
+                max_size: *play_matches.get_one::<u64>("max-download-size").unwrap(),
+                ..FetchOptions::default()
+            })
+            .with_loop(
+                if play_matches.contains_id("loop") {
+                    play_matches.get_one::<u64>("loop").copied()
+                } else {
+                    Some(1)
+                },
+                play_matches.get_one::<f64>("loop-gap").copied().unwrap(),
             );
             play.execute();
         }
         Some(("rec", rec_matches)) => {
+            check_nested(rec_matches.get_flag("nested"));
             let mut record = Record::new(
                 rec_matches.get_one::<String>("file").unwrap().to_owned(),
                 None,
                 rec_matches.get_one::<String>("command").map(Into::into),
                 rec_matches.contains_id("force"),
                 rec_matches.get_flag("stdin"),
+            )
+            .with_invalid_utf8(
+                rec_matches
+                    .get_one::<InvalidUtf8Policy>("invalid-utf8")
+                    .copied()
+                    .unwrap_or(InvalidUtf8Policy::Replace),
+            )
+            .with_input_encoding(rec_matches.get_one::<InputEncoding>("input-encoding").copied())
+            .with_upload(
+                || Box::new(Asciinema::new()),
+                rec_matches.get_flag("upload"),
+                rec_matches.get_flag("upload-delete"),
+            )
+            .with_limits(
+                rec_matches.get_one::<Duration>("max-duration").copied(),
+                rec_matches.get_one::<Duration>("stop-after-idle").copied(),
+            )
+            .with_output_caps(
+                rec_matches.get_one::<u64>("max-output-bytes").copied(),
+                rec_matches.get_one::<u64>("max-output-rate").copied(),
             );
             record.execute();
         }
+        Some(("cat", cat_matches)) => {
+            let sources: Vec<String> = cat_matches
+                .get_many::<String>("file")
+                .expect("record file required")
+                .cloned()
+                .collect();
+            let cat = Cat::new(resolve_recordings(&sources), cat_matches.get_flag("plain"));
+            cat.execute();
+        }
         Some(("auth", _)) => {
             let api_service = Asciinema::new();
             let auth = Auth::new(Box::new(api_service));
@@ -223,7 +533,16 @@ fn main() {
                 Err(_) => println!("Error: not a correct URL - e.g: https://asciinema.org"),
             }
         }
+        Some(("watch", watch_matches)) => {
+            let stream = watch_matches
+                .get_one::<String>("stream")
+                .expect("stream required");
+            let ws_url = stream_ws_url(stream)
+                .unwrap_or_else(|| Asciinema::new().get_stream_watch_url(stream));
+            Watch::new(ws_url).execute();
+        }
         Some(("stream", stream_matches)) => {
+            check_nested(stream_matches.get_flag("nested"));
             let api_service = Asciinema::new();
             let command = stream_matches
                 .get_one::<String>("command")
@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStrExt;
+
 use windows::core::{Error, Result, HSTRING, PCWSTR, PWSTR};
 use windows::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
 use windows::Win32::System::Console::HPCON;
@@ -29,17 +33,44 @@ impl Drop for Process {
     }
 }
 
-pub fn start_process(command: &str, working_dir: &str, h_pc: &mut HPCON) -> Process {
+pub fn start_process(
+    command: &str,
+    working_dir: &str,
+    env: &HashMap<String, String>,
+    h_pc: &mut HPCON,
+) -> Process {
     let mut startup_info =
         unsafe { configure_process_thread(h_pc) }.expect("couldn't setup startup_info");
-    let process_info = unsafe { run_process(&mut startup_info, command, working_dir) }
-        .expect("couldn't start process");
+    let environment = (!env.is_empty()).then(|| environment_block(env));
+    let process_info =
+        unsafe { run_process(&mut startup_info, command, working_dir, environment.as_deref()) }
+            .expect("couldn't start process");
     Process {
         startup_info,
         process_info,
     }
 }
 
+/// Build a Unicode environment block from the current environment with `env`
+/// added on top, sorted by name as `CreateProcessW` expects.
+fn environment_block(env: &HashMap<String, String>) -> Vec<u16> {
+    let mut vars: Vec<(OsString, OsString)> = std::env::vars_os()
+        .filter(|(key, _)| !env.keys().any(|k| key.eq_ignore_ascii_case(k)))
+        .chain(env.iter().map(|(k, v)| (k.into(), v.into())))
+        .collect();
+    vars.sort_by_key(|(key, _)| key.to_ascii_uppercase());
+
+    let mut block = Vec::new();
+    for (key, value) in vars {
+        block.extend(key.encode_wide());
+        block.push('=' as u16);
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
+
 unsafe fn configure_process_thread(h_pc: &mut HPCON) -> Result<STARTUPINFOEXW> {
     unsafe {
         let mut start_info = STARTUPINFOEXW::default();
@@ -93,6 +124,7 @@ unsafe fn run_process(
     startup_info: &mut STARTUPINFOEXW,
     command: &str,
     working_dir: &str,
+    environment: Option<&[u16]>,
 ) -> Result<PROCESS_INFORMATION> {
     unsafe {
         let mut p_info = PROCESS_INFORMATION::default();
@@ -110,7 +142,7 @@ unsafe fn run_process(
             None,
             false,
             EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT,
-            None,
+            environment.map(|block| block.as_ptr() as *const std::ffi::c_void),
             PCWSTR(HSTRING::from(working_dir).as_ptr()),
             &mut startup_info.StartupInfo,
             &mut p_info,
@@ -1,6 +1,9 @@
 use crate::terminal::Terminal;
 
+use std::collections::HashMap;
 use std::option::Option;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::Arc;
 
 use std::sync::mpsc::{Receiver, Sender};
 
@@ -23,7 +26,7 @@ use windows::Win32::System::Console::{
 };
 use windows::Win32::System::Pipes::CreatePipe;
 use windows::Win32::System::Threading::{
-    GetCurrentProcess, GetExitCodeProcess, WaitForSingleObject, INFINITE,
+    GetCurrentProcess, GetExitCodeProcess, TerminateProcess, WaitForSingleObject, INFINITE,
 };
 
 pub struct WindowsTerminal {
@@ -31,6 +34,12 @@ pub struct WindowsTerminal {
     stdin: isize,
     stdout: isize,
     cwd: String,
+    env: HashMap<String, String>,
+    // Handle of the running command, or 0 when none is running.
+    process: Arc<AtomicIsize>,
+    // Set once termination is requested, so a command that hasn't started
+    // yet is terminated as soon as it does.
+    stop_requested: Arc<AtomicBool>,
 
     pub width: i16,
     pub height: i16,
@@ -56,11 +65,32 @@ impl WindowsTerminal {
                     .into_string()
                     .unwrap()
             }),
+            env: HashMap::new(),
+            process: Arc::new(AtomicIsize::new(0)),
+            stop_requested: Arc::new(AtomicBool::new(false)),
             width,
             height,
         }
     }
 
+    /// Set an environment variable for the process started by `run`, on top of
+    /// the ones inherited from this process.
+    pub fn set_env(&mut self, key: &str, value: &str) {
+        self.env.insert(key.to_owned(), value.to_owned());
+    }
+
+    /// Close the pseudo console. Output still buffered in it is flushed to the
+    /// stdout pipe, which then reports end of file.
+    pub fn close(&mut self) {
+        unsafe {
+            if !self.handle.is_invalid() {
+                trace!("closing PseudoConsole handle");
+                ClosePseudoConsole(self.handle);
+                self.handle = HPCON::default();
+            }
+        }
+    }
+
     pub fn console_size() -> Option<(u16, u16)> {
         unsafe { WindowsTerminal::get_console_size().ok().map(|(x, y)| (x as u16, y as u16)) }
     }
@@ -204,9 +234,17 @@ impl WindowsTerminal {
 
 impl Terminal for WindowsTerminal {
     fn run(&mut self, command: &str) -> crate::terminal::Result<u32> {
-        let process = start_process(command, &self.cwd, &mut self.handle);
+        let process = start_process(command, &self.cwd, &self.env, &mut self.handle);
+        self.process
+            .store(process.process_info.hProcess.0 as isize, Ordering::SeqCst);
         unsafe {
+            // Termination may have been requested before the handle was stored.
+            if self.stop_requested.load(Ordering::SeqCst) {
+                trace!("terminating process");
+                let _ = TerminateProcess(process.process_info.hProcess, 1);
+            }
             WaitForSingleObject(process.process_info.hProcess, INFINITE);
+            self.process.store(0, Ordering::SeqCst);
             let mut exit_code: u32 = 0;
 
             GetExitCodeProcess(process.process_info.hProcess, &mut exit_code)
@@ -264,17 +302,33 @@ impl Terminal for WindowsTerminal {
             }
         });
     }
+
+    fn attach_terminate(&self, rx: Receiver<()>) {
+        let process = self.process.clone();
+        let stop_requested = self.stop_requested.clone();
+
+        std::thread::spawn(move || {
+            if rx.recv().is_ok() {
+                stop_requested.store(true, Ordering::SeqCst);
+                let h = process.load(Ordering::SeqCst);
+                if h != 0 {
+                    trace!("terminating process");
+                    unsafe {
+                        let _ = TerminateProcess(HANDLE(h as _), 1);
+                    }
+                }
+            }
+        });
+    }
 }
 
 impl Drop for WindowsTerminal {
     fn drop(&mut self) {
         trace!("dropping WindowsTerminal");
 
+        self.close();
+
         unsafe {
-            if !self.handle.is_invalid() {
-                trace!("closing PseudoConsole handle");
-                ClosePseudoConsole(self.handle);
-            }
             if !HANDLE(self.stdin as _).is_invalid() {
                 trace!("closing PseudoConsole stdin");
                 let _ = CloseHandle(HANDLE(self.stdin as _));
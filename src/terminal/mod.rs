@@ -15,6 +15,8 @@ pub trait Terminal {
     fn run(&mut self, command: &str) -> Result<u32>;
     fn attach_stdin(&self, rx: Receiver<(Vec<u8>, usize)>);
     fn attach_stdout(&self, tx: Sender<(Vec<u8>, usize)>);
+    /// Terminate the running command once a message arrives on `rx`.
+    fn attach_terminate(&self, rx: Receiver<()>);
 }
 
 #[cfg(test)]